@@ -57,8 +57,7 @@ fn main() -> std::io::Result<()> {
     let file = OpenOptions::new().read(true).write(true).open(filename)?;
     let mut device = FileDevice(file);
 
-    NoctFS::format(&mut device, None, Some(512))
-        .map_err(|a| Error::new(std::io::ErrorKind::Other, a.to_string()))?;
+    NoctFS::format(&mut device, None, Some(512)).map_err(|a| Error::other(a.to_string()))?;
 
     let fs = NoctFS::new(&mut device);
 
@@ -69,43 +68,100 @@ fn main() -> std::io::Result<()> {
     let mut fs = fs.unwrap();
     let re = fs.get_root_entity().unwrap();
 
-    let system_folder = fs.create_directory(re.start_block, "System");
-    let users_folder = fs.create_directory(re.start_block, "Users");
-    let apps_folder = fs.create_directory(re.start_block, "Applications");
-
-    let config_folder = fs.create_directory(system_folder.start_block, "Config");
-
-    fs.create_directory(users_folder.start_block, "NDRAEY");
-    fs.create_directory(users_folder.start_block, "User1");
-    fs.create_directory(users_folder.start_block, "User2");
-    fs.create_directory(users_folder.start_block, "User3");
-    fs.create_directory(users_folder.start_block, "Your mum");
-
-    fs.create_directory(apps_folder.start_block, "Binaries");
-    fs.create_directory(apps_folder.start_block, "Shared Libraries");
-    fs.create_directory(apps_folder.start_block, "Audacity");
-    fs.create_directory(apps_folder.start_block, "GIMP");
-    fs.create_directory(apps_folder.start_block, "Holop Rukozhop");
-    fs.create_directory(apps_folder.start_block, "Deva IDE");
-    fs.create_directory(apps_folder.start_block, "Visual Studio Code");
-    fs.create_directory(apps_folder.start_block, "Mozilla Firefox");
-    fs.create_directory(apps_folder.start_block, "Google Chrome");
-    fs.create_directory(apps_folder.start_block, "Blender");
-    fs.create_directory(apps_folder.start_block, "Web Applications");
-    fs.create_directory(apps_folder.start_block, "Ristretto");
-    fs.create_directory(apps_folder.start_block, "Pavi");
-    fs.create_directory(apps_folder.start_block, "Wireshark");
-    fs.create_directory(apps_folder.start_block, "Videolan VLC");
-    fs.create_directory(apps_folder.start_block, "Calibre");
-    fs.create_directory(apps_folder.start_block, "Qt");
-
-    fs.create_file(config_folder.start_block, "system_info.cfg");
-    let pkg_r = fs.create_file(config_folder.start_block, "pkg.cfg");
-
-    fs.delete_file(config_folder.start_block, &pkg_r);
+    let system_folder = fs.create_directory(re.start_block, "System").unwrap();
+    let users_folder = fs.create_directory(re.start_block, "Users").unwrap();
+    let apps_folder = fs.create_directory(re.start_block, "Applications").unwrap();
+
+    let config_folder = fs
+        .create_directory(system_folder.start_block, "Config")
+        .unwrap();
+
+    fs.create_directory(users_folder.start_block, "NDRAEY")
+        .unwrap();
+    fs.create_directory(users_folder.start_block, "User1")
+        .unwrap();
+    fs.create_directory(users_folder.start_block, "User2")
+        .unwrap();
+    fs.create_directory(users_folder.start_block, "User3")
+        .unwrap();
+    fs.create_directory(users_folder.start_block, "Your mum")
+        .unwrap();
+
+    fs.create_directory(apps_folder.start_block, "Binaries")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Shared Libraries")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Audacity")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "GIMP")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Holop Rukozhop")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Deva IDE")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Visual Studio Code")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Mozilla Firefox")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Google Chrome")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Blender")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Web Applications")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Ristretto")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Pavi")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Wireshark")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Videolan VLC")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Calibre")
+        .unwrap();
+    fs.create_directory(apps_folder.start_block, "Qt").unwrap();
+
+    fs.create_file(config_folder.start_block, "system_info.cfg")
+        .unwrap();
+    let pkg_r = fs
+        .create_file(config_folder.start_block, "pkg.cfg")
+        .unwrap();
+
+    fs.delete_file(config_folder.start_block, &pkg_r).unwrap();
+
+    fs.mkdir_p("/Users/NDRAEY/Documents/Projects").unwrap();
+    fs.create_file_at("/Users/NDRAEY/Documents/todo.txt")
+        .unwrap();
+    fs.create_file_at("/Users/NDRAEY/Documents/Projects/../scratch.txt")
+        .unwrap();
+    fs.remove_path("Users/NDRAEY/Documents/scratch.txt")
+        .unwrap();
+    fs.rename_path(
+        "/Users/NDRAEY/Documents/todo.txt",
+        "/Users/NDRAEY/Documents/Projects/TODO",
+    )
+    .unwrap();
+
+    {
+        let mut file = fs.open("/System/Config/system_info.cfg").unwrap();
+
+        file.write_all(b"hostname = noctis\n")?;
+        file.write_all(b"arch = x86\n")?;
+        file.seek(std::io::SeekFrom::Start(11))?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        println!("system_info.cfg tail: {:?}", contents);
+    }
+
+    println!(
+        "{:?}",
+        fs.lookup("/System/./Config/system_info.cfg").unwrap()
+    );
 
     fn list_dir(fs: &mut NoctFS<'_>, dir: &Entity, level: usize) {
-        let ents = fs.list_directory(dir.start_block);
+        let ents = fs.list_directory(dir.start_block).unwrap();
 
         for i in ents {
             let mut name = i.name.clone();
@@ -134,5 +190,9 @@ fn main() -> std::io::Result<()> {
 
     list_dir(&mut fs, &re, 0);
 
+    fs.remove_dir_all(re.start_block, &apps_folder).unwrap();
+
+    println!("After removing Applications/:\n{}", fs.check().unwrap());
+
     Ok(())
 }
@@ -59,7 +59,7 @@ fn main() -> std::io::Result<()> {
     let mut fs = NoctFS::new(&mut device).unwrap();
 
     let start_block = fs.get_root_entity().unwrap().start_block;
-    let list = fs.list_directory(start_block);
+    let list = fs.list_directory(start_block).unwrap();
 
     for i in list {
         println!("{:?}", i);
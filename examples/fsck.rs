@@ -0,0 +1,93 @@
+use std::fs::OpenOptions;
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+};
+
+use no_std_io::io::{self, Error as NoStdError, ErrorKind};
+use noctfs::check::RepairOptions;
+use noctfs::{device::Device, MountOptions, NoctFS};
+
+struct FileDevice(File);
+
+impl io::Read for FileDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|_err| {
+            eprintln!("{}", _err);
+            NoStdError::new(ErrorKind::Other, "unknown")
+        })
+    }
+}
+
+impl io::Write for FileDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(|_err| {
+            eprintln!("{}", _err);
+            NoStdError::new(ErrorKind::Other, "unknown")
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(|_err| {
+            eprintln!("{}", _err);
+            NoStdError::new(ErrorKind::Other, "unknown")
+        })
+    }
+}
+
+impl io::Seek for FileDevice {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0
+            .seek({
+                match pos {
+                    io::SeekFrom::Start(a) => std::io::SeekFrom::Start(a),
+                    io::SeekFrom::End(a) => std::io::SeekFrom::End(a),
+                    io::SeekFrom::Current(a) => std::io::SeekFrom::Current(a),
+                }
+            })
+            .map_err(|_| NoStdError::new(ErrorKind::Other, "unknown"))
+    }
+}
+
+impl Device for FileDevice {}
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let filename = args
+        .iter()
+        .rfind(|arg| !arg.starts_with("--"))
+        .expect("No filename!");
+
+    let repair = args.iter().any(|arg| arg == "--repair");
+    let options = RepairOptions {
+        dry_run: args.iter().any(|arg| arg == "--dry-run"),
+        discard_lost_chains: args.iter().any(|arg| arg == "--discard-lost"),
+    };
+
+    // Volumes that weren't unmounted cleanly are the ones that need checking most.
+    let write = repair && !options.dry_run;
+    let mount_options = MountOptions {
+        read_only: !write,
+        allow_dirty: write,
+        ..MountOptions::default()
+    };
+
+    let file = OpenOptions::new().read(true).write(write).open(filename)?;
+    let mut device = FileDevice(file);
+
+    let mut fs = NoctFS::mount(&mut device, mount_options).map_err(std::io::Error::from)?;
+    let report = if repair || options.dry_run {
+        fs.repair(options)
+    } else {
+        fs.check()
+    }
+    .map_err(std::io::Error::from)?;
+
+    print!("{}", report);
+
+    if !report.is_clean() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
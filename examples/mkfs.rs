@@ -56,8 +56,7 @@ fn main() -> std::io::Result<()> {
     let file = OpenOptions::new().read(true).write(true).open(filename)?;
     let mut device = FileDevice(file);
 
-    NoctFS::format(&mut device, None, None)
-        .map_err(|a| Error::new(std::io::ErrorKind::Other, a.to_string()))?;
+    NoctFS::format(&mut device, None, None).map_err(|a| Error::other(a.to_string()))?;
 
     Ok(())
 }
@@ -0,0 +1,200 @@
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
+use crate::{
+    entity::{Entity, MODE_MASK},
+    BlockAddress, NoctFS, NoctFSError, Result,
+};
+
+bitflags! {
+    /// Kind of access being checked, laid out like the `rwx` bits of a mode.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Access: u32 {
+        const READ = 0o4;
+        const WRITE = 0o2;
+        const EXECUTE = 0o1;
+    }
+}
+
+/// Identity of the caller, checked against entity owners and modes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups.
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            uid,
+            gid,
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn root() -> Self {
+        Self::new(0, 0)
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// Returns `true` if the caller may access the entity in the way `access` asks for.
+    ///
+    /// Root bypasses the permission bits, except that a file still needs
+    /// at least one execute bit to be executed.
+    pub fn permits(&self, entity: &Entity, access: Access) -> bool {
+        if self.is_root() {
+            return !access.contains(Access::EXECUTE)
+                || entity.is_directory()
+                || entity.mode & 0o111 != 0;
+        }
+
+        let shift = if self.uid == entity.uid {
+            6
+        } else if self.in_group(entity.gid) {
+            3
+        } else {
+            0
+        };
+
+        Access::from_bits_truncate(entity.mode >> shift).contains(access)
+    }
+}
+
+impl NoctFS<'_> {
+    /// Sets the credentials of the caller.
+    ///
+    /// With credentials set, `open`, `create_file`, `create_directory`, `list_directory`,
+    /// deletion and renames check the permission bits, and new entities are owned by the caller.
+    /// `None` (the default) turns the checks off, and new entities are owned by root.
+    pub fn set_credentials(&mut self, credentials: Option<Credentials>) {
+        self.credentials = credentials;
+    }
+
+    pub fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    pub(crate) fn check_access(&self, entity: &Entity, access: Access) -> Result<()> {
+        match &self.credentials {
+            Some(credentials) if !credentials.permits(entity, access) => {
+                Err(NoctFSError::PermissionDenied)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks access to the directory at `directory_block` through its `.` record,
+    /// which mirrors the owner and mode of the directory's own record.
+    pub(crate) fn check_directory_access(
+        &mut self,
+        directory_block: BlockAddress,
+        access: Access,
+    ) -> Result<()> {
+        if self.credentials.is_none() {
+            return Ok(());
+        }
+
+        let this = self.find_entity(directory_block, ".")?;
+
+        self.check_access(&this, access)
+    }
+
+    /// Owner of newly created entities.
+    pub(crate) fn new_owner(&self) -> (u32, u32) {
+        self.credentials
+            .as_ref()
+            .map_or((0, 0), |credentials| (credentials.uid, credentials.gid))
+    }
+
+    /// Changes the permission bits of the entity. Only its owner and root may do that.
+    pub fn chmod(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        mode: u32,
+    ) -> Result<Entity> {
+        let current = self.current_record(directory_block, entity)?;
+
+        if let Some(credentials) = &self.credentials {
+            if !credentials.is_root() && credentials.uid != current.uid {
+                return Err(NoctFSError::PermissionDenied);
+            }
+        }
+
+        let mut new_entity = current.clone();
+        new_entity.mode = mode & MODE_MASK;
+
+        self.update_ownership(directory_block, &current, new_entity)
+    }
+
+    /// Changes the owner and/or group of the entity.
+    ///
+    /// Only root may give an entity away. The owner may change its group
+    /// to one of their own groups, which clears the set-user-ID and set-group-ID bits.
+    pub fn chown(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<Entity> {
+        let current = self.current_record(directory_block, entity)?;
+        let mut new_entity = current.clone();
+
+        if let Some(credentials) = &self.credentials {
+            if !credentials.is_root() {
+                let gives_away = uid.is_some_and(|uid| uid != current.uid);
+                let foreign_group = gid.is_some_and(|gid| !credentials.in_group(gid));
+
+                if credentials.uid != current.uid || gives_away || foreign_group {
+                    return Err(NoctFSError::PermissionDenied);
+                }
+
+                new_entity.mode &= !0o6000;
+            }
+        }
+
+        new_entity.uid = uid.unwrap_or(current.uid);
+        new_entity.gid = gid.unwrap_or(current.gid);
+
+        self.update_ownership(directory_block, &current, new_entity)
+    }
+
+    /// Writes the new owner and mode to the entity's record and, for directories, to its `.` record.
+    fn update_ownership(
+        &mut self,
+        directory_block: BlockAddress,
+        current: &Entity,
+        mut new_entity: Entity,
+    ) -> Result<Entity> {
+        new_entity.times.changed = self.clock.now();
+
+        // Both copies change together, or permission checks would see the old `.` one.
+        self.atomic(|fs| {
+            // The root's `.` record is its only record, and is rewritten below.
+            if new_entity.is_directory() && !fs.is_root_entity(current) {
+                let this = fs.find_entity(new_entity.start_block, ".")?;
+
+                let mut new_this = this.clone();
+                new_this.uid = new_entity.uid;
+                new_this.gid = new_entity.gid;
+                new_this.mode = new_entity.mode;
+
+                fs.overwrite_entity_header(new_entity.start_block, &this, &new_this)?;
+            }
+
+            fs.update_record(directory_block, current, &new_entity)?;
+
+            Ok(new_entity)
+        })
+    }
+}
@@ -1,23 +1,274 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String};
+use bitflags::bitflags;
+use core::fmt;
+use no_std_io::io::SeekFrom::{End, Start};
 
-use crate::FILESYSTEM_CODENAME;
+use crate::{
+    device::ReadDevice, BlockAddress, NoctFSError, Result, ALLOWED_BLOCK_SIZES, BLOCK_ADDRESS_SIZE,
+    FILESYSTEM_CODENAME,
+};
 
 const BOOTCODE: &[u8; 512] = include_bytes!("../static/bootcode.bin");
 
-#[derive(Debug)]
-#[repr(C, packed)]
+/// Size of the bootsector, which is also the smallest allowed sector size.
+pub(crate) const BOOTSECTOR_SIZE: usize = 512;
+
+/// Longest volume label, in bytes.
+pub const MAX_LABEL_LENGTH: usize = 64;
+
+/// Offset of the label and UUID, past the boot code and its message.
+const VOLUME_ID_OFFSET: usize = 256;
+
+/// Offset of the journal block, past the label.
+const JOURNAL_BLOCK_OFFSET: usize = VOLUME_ID_OFFSET + 16 + MAX_LABEL_LENGTH;
+
+/// On-disk format version written by this driver.
+/// Volumes formatted before versioning have 0 and no feature flags.
+pub const FORMAT_VERSION: u16 = 1;
+
+bitflags! {
+    /// Features a driver may ignore and still read and write the volume safely.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CompatFeatures: u32 {
+        /// Metadata journal. It's empty whenever `RECOVER` is clear,
+        /// so drivers without journaling can ignore it.
+        const JOURNAL = 1 << 0;
+    }
+}
+
+bitflags! {
+    /// Features a driver has to understand to write the volume, but not to read it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RoCompatFeatures: u32 {
+        /// Free block bitmap, which has to be kept in sync with the block map.
+        const FREE_MAP = 1 << 0;
+        /// Hard link table. Chains listed in it can't be freed along with a single record.
+        const LINK_TABLE = 1 << 1;
+    }
+}
+
+bitflags! {
+    /// Features a driver has to understand to read the volume at all.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IncompatFeatures: u32 {
+        /// The journal may hold a transaction that has to be replayed before the volume is used.
+        /// Set for as long as a journaled volume is mounted read-write.
+        const RECOVER = 1 << 0;
+    }
+}
+
+/// Fields follow the 3-byte jump of the boot code, all little-endian:
+///  [3..11]  (8 bytes) - Filesystem codename
+///  [11..13] (2 bytes) - Sector size
+///  [13..17] (4 bytes) - Block size
+///  [17..21] (4 bytes) - Block count
+///  [21..29] (8 bytes) - Root directory block
+///  [29..37] (8 bytes) - Free block bitmap block
+///  [37..45] (8 bytes) - Link table block
+///  [45..47] (2 bytes) - Format version
+///  [47..51] (4 bytes) - Compatible features
+///  [51..55] (4 bytes) - Read-only compatible features
+///  [55..59] (4 bytes) - Incompatible features
+///  [59..61] (2 bytes) - Volume state
+///
+/// and after the boot code:
+///  [256..272] (16 bytes) - Volume UUID
+///  [272..336] (64 bytes) - Volume label in UTF-8, padded with zeroes
+///  [336..344] (8 bytes)  - Journal block
+#[derive(Debug, Clone)]
 pub struct BootSector {
     pub(crate) filesystem_codename: [u8; 8],
     pub(crate) sector_size: u16,
     pub(crate) block_size: u32,
     pub(crate) block_map_count: u32,
     pub(crate) first_root_entity_block: u64,
+    /// First block of the free block bitmap chain.
+    /// Volumes formatted before the bitmap existed have 0 here.
+    pub(crate) free_map_block: u64,
+    /// First block of the hard link table chain, 0 if no chain has more than one record.
+    pub(crate) link_table_block: u64,
+    pub(crate) version: u16,
+    pub(crate) compat_features: CompatFeatures,
+    pub(crate) ro_compat_features: RoCompatFeatures,
+    pub(crate) incompat_features: IncompatFeatures,
+    pub(crate) uuid: Uuid,
+    pub(crate) label: [u8; MAX_LABEL_LENGTH],
+    pub(crate) state: VolumeState,
+    /// First block of the journal chain, 0 if the volume has no journal.
+    pub(crate) journal_block: u64,
+}
+
+/// Whether the volume was unmounted cleanly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeState {
+    /// Unmounted cleanly, or never mounted read-write since formatting.
+    /// Volumes formatted before the state existed read as clean.
+    Clean,
+    /// Mounted read-write and not unmounted yet, or a crash happened while it was.
+    /// The volume may be inconsistent and should be checked.
+    Dirty,
+}
+
+impl VolumeState {
+    fn to_raw(self) -> u16 {
+        match self {
+            Self::Clean => 0,
+            Self::Dirty => 1,
+        }
+    }
+
+    /// Unknown states are taken as dirty, to be on the safe side.
+    fn from_raw(raw: u16) -> Self {
+        match raw {
+            0 => Self::Clean,
+            _ => Self::Dirty,
+        }
+    }
+}
+
+/// 128-bit volume identifier. All zeroes means the volume has none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    pub const NIL: Self = Self([0; 16]);
+
+    pub fn is_nil(&self) -> bool {
+        *self == Self::NIL
+    }
+
+    /// Random (version 4) UUID.
+    #[cfg(feature = "std")]
+    pub fn random() -> Self {
+        use std::hash::{BuildHasher, Hasher};
+
+        let mut bytes = [0u8; 16];
+
+        // Every `RandomState` is keyed differently, off a random per-process seed.
+        for half in bytes.chunks_exact_mut(8) {
+            let hasher = std::collections::hash_map::RandomState::new().build_hasher();
+
+            half.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        Self(bytes)
+    }
+}
+
+/// Formats the UUID in the usual hyphenated form.
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (nr, byte) in self.0.iter().enumerate() {
+            if matches!(nr, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that `label` fits into the bootsector and returns it zero-padded.
+pub(crate) fn encode_label(label: &str) -> Result<[u8; MAX_LABEL_LENGTH]> {
+    if label.len() > MAX_LABEL_LENGTH || label.contains('\0') {
+        return Err(NoctFSError::InvalidLabel);
+    }
+
+    let mut raw = [0u8; MAX_LABEL_LENGTH];
+    raw[..label.len()].copy_from_slice(label.as_bytes());
+
+    Ok(raw)
+}
+
+/// Geometry and identity of a volume, as described by its bootsector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeInfo {
+    pub sector_size: usize,
+    pub block_size: usize,
+    pub block_count: u64,
+    pub root_block: BlockAddress,
+    /// Byte offset of block 0 on the device.
+    pub data_offset: u64,
+    /// Bytes the volume spans, from the bootsector to the end of the last block.
+    pub size: u64,
+    pub version: u16,
+    /// Feature flags, possibly including ones this driver doesn't know.
+    pub compat_features: CompatFeatures,
+    pub ro_compat_features: RoCompatFeatures,
+    pub incompat_features: IncompatFeatures,
+    pub uuid: Uuid,
+    /// Empty if the volume has no label.
+    pub label: String,
+    pub state: VolumeState,
 }
 
 impl BootSector {
+    /// Reads and validates the bootsector at the start of the device.
+    ///
+    /// Fails with `SignatureNotValid` if the device doesn't hold a NoctFS volume,
+    /// and with `InvalidBootSector` if it holds a damaged one.
+    pub(crate) fn read(device: &mut dyn ReadDevice) -> Result<Self> {
+        let mut data = [0u8; BOOTSECTOR_SIZE];
+
+        let device_size = device.seek(End(0))?;
+
+        device.seek(Start(0))?;
+        device.read_exact(&mut data)?;
+
+        let bootsector = Self::from_raw(&data)?;
+
+        bootsector.validate(device_size)?;
+
+        Ok(bootsector)
+    }
+
+    /// Identifies and describes the NoctFS volume on the device without mounting it.
+    pub fn probe(device: &mut dyn ReadDevice) -> Result<VolumeInfo> {
+        Ok(Self::read(device)?.info())
+    }
+
+    /// Public view of the fields, with offsets computed from them.
+    pub fn info(&self) -> VolumeInfo {
+        let block_count = self.block_map_count as u64;
+        let data_offset = self.sector_size as u64 + block_count * BLOCK_ADDRESS_SIZE as u64;
+
+        VolumeInfo {
+            sector_size: self.sector_size as usize,
+            block_size: self.block_size as usize,
+            block_count,
+            root_block: self.first_root_entity_block,
+            data_offset,
+            size: data_offset + block_count * self.block_size as u64,
+            version: self.version,
+            compat_features: self.compat_features,
+            ro_compat_features: self.ro_compat_features,
+            incompat_features: self.incompat_features,
+            uuid: self.uuid,
+            label: self.label(),
+            state: self.state,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        let length = self
+            .label
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(MAX_LABEL_LENGTH);
+
+        String::from_utf8_lossy(&self.label[..length]).into()
+    }
+
+    /// Lays out a volume filling `device_size` bytes, with the root directory in block 1.
     pub fn with_data(device_size: usize, sector_size: u16, block_size: u32) -> Self {
-        let block_map_count = device_size / block_size as usize;
-        let first_root_entry = sector_size as usize + block_map_count;
+        // Every block takes a block map entry besides its data.
+        let block_map_count = device_size.saturating_sub(sector_size as usize)
+            / (block_size as usize + BLOCK_ADDRESS_SIZE);
 
         let mut codename: [u8; 8] = [0; 8];
         codename.copy_from_slice(FILESYSTEM_CODENAME);
@@ -26,25 +277,181 @@ impl BootSector {
             filesystem_codename: codename,
             sector_size,
             block_size,
-            block_map_count: block_map_count as u32,
-            first_root_entity_block: (first_root_entry / sector_size as usize) as u64,
+            block_map_count: block_map_count.min(u32::MAX as usize) as u32,
+            first_root_entity_block: 1,
+            free_map_block: 0,
+            link_table_block: 0,
+            version: FORMAT_VERSION,
+            compat_features: CompatFeatures::empty(),
+            ro_compat_features: RoCompatFeatures::empty(),
+            incompat_features: IncompatFeatures::empty(),
+            uuid: Uuid::NIL,
+            label: [0; MAX_LABEL_LENGTH],
+            state: VolumeState::Clean,
+            journal_block: 0,
         }
     }
 
+    /// Checks that this driver understands every feature the volume uses.
+    /// Unknown read-only compatible features only matter if the volume is to be written.
+    pub(crate) fn check_features(&self, read_only: bool) -> Result<()> {
+        if self.version > FORMAT_VERSION {
+            return Err(NoctFSError::UnsupportedVersion {
+                version: self.version,
+            });
+        }
+
+        let incompat = self.incompat_features.bits() & !IncompatFeatures::all().bits();
+        let ro_compat = match read_only {
+            true => 0,
+            false => self.ro_compat_features.bits() & !RoCompatFeatures::all().bits(),
+        };
+
+        if incompat != 0 || ro_compat != 0 {
+            return Err(NoctFSError::UnsupportedFeatures {
+                incompat,
+                ro_compat,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the fields describe a volume that fits on a device of `device_size` bytes.
+    ///
+    /// Volumes formatted by older versions left no room for the block map when counting blocks,
+    /// so their last blocks lie past the end of the device. Those are still accepted,
+    /// as long as the block map fits and the blocks alone wouldn't outgrow the device.
+    pub(crate) fn validate(&self, device_size: u64) -> Result<()> {
+        let invalid = |field| Err(NoctFSError::InvalidBootSector { field });
+
+        let block_count = self.block_map_count as u64;
+        let map_end = self.sector_size as u64 + block_count * BLOCK_ADDRESS_SIZE as u64;
+        let blocks_size = block_count * self.block_size as u64;
+
+        if !self.sector_size.is_power_of_two() || (self.sector_size as usize) < BOOTSECTOR_SIZE {
+            return invalid("sector size");
+        }
+
+        if !ALLOWED_BLOCK_SIZES.contains(&self.block_size) {
+            return invalid("block size");
+        }
+
+        // Block 0 is reserved, so there's no room for the root below 2 blocks.
+        if block_count < 2 || map_end > device_size || blocks_size > device_size {
+            return invalid("block count");
+        }
+
+        if self.first_root_entity_block == 0 || self.first_root_entity_block >= block_count {
+            return invalid("root directory block");
+        }
+
+        let has_free_map = self.ro_compat_features.contains(RoCompatFeatures::FREE_MAP);
+        let has_link_table = self
+            .ro_compat_features
+            .contains(RoCompatFeatures::LINK_TABLE);
+
+        if self.free_map_block >= block_count || has_free_map != (self.free_map_block != 0) {
+            return invalid("free block bitmap block");
+        }
+
+        if self.link_table_block >= block_count || has_link_table != (self.link_table_block != 0) {
+            return invalid("link table block");
+        }
+
+        let has_journal = self.compat_features.contains(CompatFeatures::JOURNAL);
+
+        if self.journal_block >= block_count || has_journal != (self.journal_block != 0) {
+            return invalid("journal block");
+        }
+
+        let label_length = self
+            .label
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(MAX_LABEL_LENGTH);
+
+        if core::str::from_utf8(&self.label[..label_length]).is_err() {
+            return invalid("label");
+        }
+
+        Ok(())
+    }
+
     pub fn as_raw(&self) -> Box<[u8]> {
-        let mut sector: [u8; 512] = *BOOTCODE;
-        let self_size = core::mem::size_of::<Self>();
-        let raw_ptr = self as *const Self;
-        let raw_data = unsafe { core::slice::from_raw_parts(raw_ptr as *const u8, self_size) };
+        let mut sector: [u8; BOOTSECTOR_SIZE] = *BOOTCODE;
+
+        sector[3..11].copy_from_slice(&self.filesystem_codename);
+        sector[11..13].copy_from_slice(&self.sector_size.to_le_bytes());
+        sector[13..17].copy_from_slice(&self.block_size.to_le_bytes());
+        sector[17..21].copy_from_slice(&self.block_map_count.to_le_bytes());
+        sector[21..29].copy_from_slice(&self.first_root_entity_block.to_le_bytes());
+        sector[29..37].copy_from_slice(&self.free_map_block.to_le_bytes());
+        sector[37..45].copy_from_slice(&self.link_table_block.to_le_bytes());
+        sector[45..47].copy_from_slice(&self.version.to_le_bytes());
+        sector[47..51].copy_from_slice(&self.compat_features.bits().to_le_bytes());
+        sector[51..55].copy_from_slice(&self.ro_compat_features.bits().to_le_bytes());
+        sector[55..59].copy_from_slice(&self.incompat_features.bits().to_le_bytes());
+        sector[59..61].copy_from_slice(&self.state.to_raw().to_le_bytes());
 
-        sector[3..self_size + 3].copy_from_slice(raw_data);
+        let label_offset = VOLUME_ID_OFFSET + 16;
+
+        sector[VOLUME_ID_OFFSET..label_offset].copy_from_slice(&self.uuid.0);
+        sector[label_offset..label_offset + MAX_LABEL_LENGTH].copy_from_slice(&self.label);
+        sector[JOURNAL_BLOCK_OFFSET..JOURNAL_BLOCK_OFFSET + 8]
+            .copy_from_slice(&self.journal_block.to_le_bytes());
 
         Box::new(sector)
     }
 
-    pub fn from_raw(data: &[u8; 512]) -> Self {
-        let raw_ptr = data[3..].as_ptr() as *const Self;
+    /// Parses the bootsector, failing with `SignatureNotValid` if it's not a NoctFS one.
+    ///
+    /// Fields aren't validated, see [`BootSector::probe`] for that.
+    pub fn from_raw(data: &[u8; BOOTSECTOR_SIZE]) -> Result<Self> {
+        let u16_at = |at: usize| u16::from_le_bytes(data[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+
+        if &data[3..11] != FILESYSTEM_CODENAME {
+            return Err(NoctFSError::SignatureNotValid);
+        }
+
+        let mut bootsector = Self {
+            filesystem_codename: data[3..11].try_into().unwrap(),
+            sector_size: u16_at(11),
+            block_size: u32_at(13),
+            block_map_count: u32_at(17),
+            first_root_entity_block: u64_at(21),
+            free_map_block: u64_at(29),
+            link_table_block: u64_at(37),
+            version: u16_at(45),
+            compat_features: CompatFeatures::from_bits_retain(u32_at(47)),
+            ro_compat_features: RoCompatFeatures::from_bits_retain(u32_at(51)),
+            incompat_features: IncompatFeatures::from_bits_retain(u32_at(55)),
+            state: VolumeState::from_raw(u16_at(59)),
+            // Older volumes have the zeroed tail of the boot code here.
+            uuid: Uuid(
+                data[VOLUME_ID_OFFSET..VOLUME_ID_OFFSET + 16]
+                    .try_into()
+                    .unwrap(),
+            ),
+            label: data[VOLUME_ID_OFFSET + 16..VOLUME_ID_OFFSET + 16 + MAX_LABEL_LENGTH]
+                .try_into()
+                .unwrap(),
+            journal_block: u64_at(JOURNAL_BLOCK_OFFSET),
+        };
+
+        // Unversioned volumes got the bitmap and the link table before there were flags for them.
+        if bootsector.version == 0 {
+            bootsector
+                .ro_compat_features
+                .set(RoCompatFeatures::FREE_MAP, bootsector.free_map_block != 0);
+            bootsector.ro_compat_features.set(
+                RoCompatFeatures::LINK_TABLE,
+                bootsector.link_table_block != 0,
+            );
+        }
 
-        unsafe { raw_ptr.read() }
+        Ok(bootsector)
     }
 }
@@ -0,0 +1,85 @@
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use crate::{BlockAddress, BLOCK_ADDRESS_SIZE};
+
+/// Number of block map entries loaded from the device at once.
+pub(crate) const ENTRIES_PER_PAGE: u64 = 512;
+
+pub(crate) const PAGE_SIZE: usize = ENTRIES_PER_PAGE as usize * BLOCK_ADDRESS_SIZE;
+
+pub(crate) struct Page {
+    pub(crate) entries: Box<[BlockAddress]>,
+    pub(crate) dirty: bool,
+    last_used: u64,
+}
+
+/// Write-back cache of the block map, split into pages of `ENTRIES_PER_PAGE` entries.
+///
+/// When the cache is full, the least recently used page is evicted
+/// and has to be written back by the caller if it's dirty.
+pub(crate) struct BlockMapCache {
+    pages: BTreeMap<u64, Page>,
+    max_pages: usize,
+    tick: u64,
+}
+
+impl BlockMapCache {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            max_pages: Self::pages_for(limit),
+            tick: 0,
+        }
+    }
+
+    fn pages_for(limit: usize) -> usize {
+        (limit / PAGE_SIZE).max(1)
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: usize) {
+        self.max_pages = Self::pages_for(limit);
+    }
+
+    pub(crate) fn contains(&self, page_nr: u64) -> bool {
+        self.pages.contains_key(&page_nr)
+    }
+
+    pub(crate) fn get_mut(&mut self, page_nr: u64) -> Option<&mut Page> {
+        self.tick += 1;
+
+        let page = self.pages.get_mut(&page_nr)?;
+        page.last_used = self.tick;
+
+        Some(page)
+    }
+
+    pub(crate) fn insert(&mut self, page_nr: u64, entries: Box<[BlockAddress]>) {
+        self.pages.insert(
+            page_nr,
+            Page {
+                entries,
+                dirty: false,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    /// Removes the least recently used page if there's no room for another one.
+    pub(crate) fn evict(&mut self) -> Option<(u64, Page)> {
+        if self.pages.len() < self.max_pages {
+            return None;
+        }
+
+        let (&page_nr, _) = self.pages.iter().min_by_key(|(_, page)| page.last_used)?;
+
+        self.pages.remove(&page_nr).map(|page| (page_nr, page))
+    }
+
+    pub(crate) fn dirty_pages(&self) -> Vec<u64> {
+        self.pages
+            .iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&page_nr, _)| page_nr)
+            .collect()
+    }
+}
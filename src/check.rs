@@ -0,0 +1,910 @@
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+use crate::{
+    entity::Entity, parse_records, BlockAddress, NoctFS, NoctFSError, Result, END_OF_CHAIN,
+    MAX_NAME_LENGTH,
+};
+
+/// Directory that recovered chains are linked into.
+pub const LOST_AND_FOUND: &str = "/lost+found";
+
+/// A single inconsistency found by [`NoctFS::check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A directory record couldn't be parsed. Records after it are unreachable.
+    BadRecord { directory: String, offset: usize },
+    /// Entity name is empty, too long or contains forbidden characters.
+    BadName { directory: String, name: String },
+    /// Two records in one directory share the same name.
+    DuplicateName { directory: String, name: String },
+    /// Entity's chain links to a free or nonexistent block, or loops.
+    BrokenChain { path: String, block: BlockAddress },
+    /// Block belongs to the chains of two different entities.
+    CrossLinked {
+        block: BlockAddress,
+        first: String,
+        second: String,
+    },
+    /// File size doesn't match the number of blocks in its chain.
+    SizeMismatch {
+        path: String,
+        size: u64,
+        blocks: usize,
+    },
+    /// `.` or `..` of a directory is missing or points to the wrong block.
+    BadDotEntry {
+        directory: String,
+        name: &'static str,
+        expected: BlockAddress,
+        found: Option<BlockAddress>,
+    },
+    /// Chain is marked as used, but no entity refers to it.
+    LeakedChain {
+        start_block: BlockAddress,
+        blocks: usize,
+    },
+    /// Block is marked as used, but it's not reachable from any chain start.
+    LeakedBlock { block: BlockAddress },
+    /// Free block bitmap disagrees with the block map.
+    FreeMapMismatch { block: BlockAddress, used: bool },
+    /// Link table disagrees with the records pointing to a file's chain.
+    LinkCountMismatch {
+        start_block: BlockAddress,
+        recorded: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRecord { directory, offset } => {
+                write!(f, "{directory}: unparseable record at offset {offset}")
+            }
+            Self::BadName { directory, name } => {
+                write!(f, "{directory}: invalid name {name:?}")
+            }
+            Self::DuplicateName { directory, name } => {
+                write!(f, "{directory}: duplicate name {name:?}")
+            }
+            Self::BrokenChain { path, block } => {
+                write!(f, "{path}: broken chain at block {block}")
+            }
+            Self::CrossLinked {
+                block,
+                first,
+                second,
+            } => write!(f, "block {block} is shared by {first} and {second}"),
+            Self::SizeMismatch { path, size, blocks } => {
+                write!(
+                    f,
+                    "{path}: size {size} doesn't fit a chain of {blocks} blocks"
+                )
+            }
+            Self::BadDotEntry {
+                directory,
+                name,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "{directory}: `{name}` points to block {found} instead of {expected}"
+            ),
+            Self::BadDotEntry {
+                directory, name, ..
+            } => write!(f, "{directory}: `{name}` is missing"),
+            Self::LeakedChain {
+                start_block,
+                blocks,
+            } => write!(
+                f,
+                "unreachable chain of {blocks} blocks starting at block {start_block}"
+            ),
+            Self::LeakedBlock { block } => write!(f, "unreachable block {block}"),
+            Self::FreeMapMismatch { block, used } => write!(
+                f,
+                "block {block} is {} in the block map, but not in the free block bitmap",
+                if *used { "used" } else { "free" }
+            ),
+            Self::LinkCountMismatch {
+                start_block,
+                recorded,
+                found,
+            } => write!(
+                f,
+                "chain starting at block {start_block} has {found} links, but the link table records {recorded}"
+            ),
+        }
+    }
+}
+
+/// A fix performed (or, in a dry run, planned) by [`NoctFS::repair`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// Zero directory data from the first unparseable record to the end.
+    DropRecords {
+        directory: String,
+        directory_block: BlockAddress,
+        offset: usize,
+    },
+    /// Cut the chain down to the number of blocks its size needs, freeing the tail.
+    TruncateChain {
+        path: String,
+        start_block: BlockAddress,
+        blocks: usize,
+    },
+    /// Remove a record whose chain starts at a free or nonexistent block.
+    DropRecord {
+        path: String,
+        directory_block: BlockAddress,
+        name: String,
+    },
+    /// End a broken chain at `block`, the last one before the break.
+    EndChain { path: String, block: BlockAddress },
+    /// Shrink a file to the data its chain still holds.
+    SetSize {
+        path: String,
+        directory_block: BlockAddress,
+        name: String,
+        size: u64,
+    },
+    /// Point `.` or `..` to the right directory, recreating the record if it's missing.
+    RewriteDotEntry {
+        directory: String,
+        directory_block: BlockAddress,
+        name: &'static str,
+        target: BlockAddress,
+    },
+    /// Link a leaked chain into [`LOST_AND_FOUND`] as a file.
+    RecoverChain {
+        start_block: BlockAddress,
+        blocks: usize,
+    },
+    /// Zero the block map entry of an unreachable block.
+    FreeBlock { block: BlockAddress },
+    /// Make the free block bitmap agree with the block map.
+    FixFreeMap { block: BlockAddress, used: bool },
+    /// Record the directories actually holding links to the chain in the link table.
+    FixLinkCount {
+        start_block: BlockAddress,
+        directories: Vec<BlockAddress>,
+    },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DropRecords {
+                directory, offset, ..
+            } => write!(f, "{directory}: drop records from offset {offset}"),
+            Self::TruncateChain { path, blocks, .. } => {
+                write!(f, "{path}: truncate chain to {blocks} blocks")
+            }
+            Self::DropRecord { path, .. } => write!(f, "{path}: remove record"),
+            Self::EndChain { path, block } => write!(f, "{path}: end chain at block {block}"),
+            Self::SetSize { path, size, .. } => write!(f, "{path}: set size to {size}"),
+            Self::RewriteDotEntry {
+                directory,
+                name,
+                target,
+                ..
+            } => write!(f, "{directory}: point `{name}` to block {target}"),
+            Self::RecoverChain {
+                start_block,
+                blocks,
+            } => write!(
+                f,
+                "recover chain of {blocks} blocks starting at block {start_block} into {LOST_AND_FOUND}"
+            ),
+            Self::FreeBlock { block } => write!(f, "free block {block}"),
+            Self::FixFreeMap { block, used } => write!(
+                f,
+                "mark block {block} as {} in the free block bitmap",
+                if *used { "used" } else { "free" }
+            ),
+            Self::FixLinkCount {
+                start_block,
+                directories,
+            } => write!(
+                f,
+                "set link count of chain starting at block {start_block} to {}",
+                directories.len()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepairOptions {
+    /// Only plan the repairs, don't write anything.
+    pub dry_run: bool,
+    /// Free leaked chains instead of recovering them into [`LOST_AND_FOUND`].
+    pub discard_lost_chains: bool,
+}
+
+/// Result of a consistency check.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub problems: Vec<Problem>,
+    /// Repairs made by [`NoctFS::repair`], empty for a plain check.
+    pub repairs: Vec<Repair>,
+    /// Whether `repairs` were only planned.
+    pub dry_run: bool,
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub used_blocks: u64,
+    pub total_blocks: u64,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Files:        {}", self.files)?;
+        writeln!(f, "Directories:  {}", self.directories)?;
+        writeln!(f, "Symlinks:     {}", self.symlinks)?;
+        writeln!(
+            f,
+            "Used blocks:  {} / {}",
+            self.used_blocks, self.total_blocks
+        )?;
+
+        if self.is_clean() {
+            return writeln!(f, "No problems found.");
+        }
+
+        writeln!(f, "Problems:     {}", self.problems.len())?;
+
+        for problem in &self.problems {
+            writeln!(f, "  - {problem}")?;
+        }
+
+        if self.repairs.is_empty() {
+            return Ok(());
+        }
+
+        if self.dry_run {
+            writeln!(f, "Planned repairs (dry run): {}", self.repairs.len())?;
+        } else {
+            writeln!(f, "Repairs:      {}", self.repairs.len())?;
+        }
+
+        for repair in &self.repairs {
+            writeln!(f, "  - {repair}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn join_path(directory: &str, name: &str) -> String {
+    if directory == "/" {
+        format!("/{name}")
+    } else {
+        format!("{directory}/{name}")
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LENGTH && !name.contains(['/', '\0'])
+}
+
+/// State gathered while walking the tree.
+#[derive(Default)]
+struct Walk {
+    report: CheckReport,
+    /// Fixes for the problems found, applied only by `repair`.
+    repairs: Vec<Repair>,
+    discard_lost_chains: bool,
+    /// Owner path of every block reachable from the root.
+    owners: BTreeMap<BlockAddress, String>,
+    /// Blocks claimed by more than one chain. Repairs never free these.
+    shared: BTreeSet<BlockAddress>,
+    /// Directories holding a record of every file chain, one entry per record.
+    links: BTreeMap<BlockAddress, Vec<BlockAddress>>,
+    /// Start blocks of records whose chain starts at a free or nonexistent block.
+    dangling: BTreeSet<BlockAddress>,
+    /// A directory couldn't be read whole, so blocks that look leaked may still be in use.
+    incomplete: bool,
+}
+
+impl Walk {
+    /// Claims the blocks of a chain for `path`. Returns `false` if any block was already taken.
+    fn claim(&mut self, chain: &[BlockAddress], path: &str) -> bool {
+        let mut exclusive = true;
+
+        for &block in chain {
+            match self.owners.get(&block) {
+                Some(first) => {
+                    self.report.problems.push(Problem::CrossLinked {
+                        block,
+                        first: first.clone(),
+                        second: path.to_string(),
+                    });
+                    self.shared.insert(block);
+                    exclusive = false;
+                }
+                None => {
+                    self.owners.insert(block, path.to_string());
+                }
+            }
+        }
+
+        exclusive
+    }
+}
+
+impl NoctFS<'_> {
+    /// Walks the whole volume and reports every inconsistency found. Nothing is written.
+    pub fn check(&mut self) -> Result<CheckReport> {
+        Ok(self.walk(Walk::default())?.report)
+    }
+
+    /// Checks the volume and fixes what can be fixed.
+    ///
+    /// Chains hidden behind dropped directory records, or in the part of a directory's chain
+    /// past a break, only become visible as leaks afterwards, so another run may have more
+    /// to recover. A volume mounted dirty is marked clean again on unmount once it has been
+    /// repaired.
+    pub fn repair(&mut self, options: RepairOptions) -> Result<CheckReport> {
+        let mut walk = self.walk(Walk {
+            discard_lost_chains: options.discard_lost_chains,
+            ..Walk::default()
+        })?;
+
+        if !options.dry_run {
+            self.check_writable()?;
+
+            for repair in &walk.repairs {
+                self.apply_repair(repair, &walk.shared)?;
+            }
+
+            // Dropped and recovered records aren't tracked one by one.
+            self.entity_counts = None;
+
+            self.sync()?;
+            self.mark_checked();
+        }
+
+        walk.report.repairs = walk.repairs;
+        walk.report.dry_run = options.dry_run;
+
+        Ok(walk.report)
+    }
+
+    fn walk(&mut self, mut walk: Walk) -> Result<Walk> {
+        walk.report.total_blocks = self.bootsector.block_map_count as u64;
+        walk.owners.insert(0, "(reserved)".to_string());
+
+        let metadata = [
+            (self.bootsector.free_map_block, "(free block bitmap)"),
+            (self.bootsector.link_table_block, "(link table)"),
+            (self.bootsector.journal_block, "(journal)"),
+        ];
+
+        for (start_block, path) in metadata {
+            if start_block == 0 {
+                continue;
+            }
+
+            match self.get_chain(start_block) {
+                Ok(chain) => {
+                    walk.claim(&chain, path);
+                }
+                Err(NoctFSError::Corrupted { block }) => {
+                    walk.report.problems.push(Problem::BrokenChain {
+                        path: path.to_string(),
+                        block,
+                    })
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.check_tree(&mut walk)?;
+        self.check_link_counts(&mut walk);
+        self.check_block_map(&mut walk)?;
+
+        Ok(walk)
+    }
+
+    fn apply_repair(&mut self, repair: &Repair, shared: &BTreeSet<BlockAddress>) -> Result<()> {
+        match *repair {
+            Repair::DropRecords {
+                directory_block,
+                offset,
+                ..
+            } => {
+                let length = self.read_chain_data_vec(directory_block)?.len();
+
+                self.write_blocks_data(directory_block, &vec![0u8; length - offset], offset as _)?;
+            }
+            Repair::TruncateChain {
+                start_block,
+                blocks,
+                ..
+            } => {
+                let chain = self.get_chain(start_block)?;
+
+                if chain.len() > blocks {
+                    self.write_block(chain[blocks - 1], END_OF_CHAIN)?;
+
+                    for &block in &chain[blocks..] {
+                        if !shared.contains(&block) {
+                            self.write_block(block, 0)?;
+                        }
+                    }
+                }
+            }
+            Repair::DropRecord {
+                directory_block,
+                ref name,
+                ..
+            } => {
+                let entity = self.find_entity(directory_block, name)?;
+
+                self.remove_record(directory_block, &entity)?;
+            }
+            Repair::EndChain { block, .. } => self.write_block(block, END_OF_CHAIN)?,
+            Repair::SetSize {
+                directory_block,
+                ref name,
+                size,
+                ..
+            } => {
+                let entity = self.find_entity(directory_block, name)?;
+                let mut fixed = entity.clone();
+                fixed.size = size;
+
+                self.overwrite_entity_header(directory_block, &entity, &fixed)?;
+            }
+            Repair::RewriteDotEntry {
+                directory_block,
+                name,
+                target,
+                ..
+            } => match self.find_entity(directory_block, name) {
+                Ok(entity) => {
+                    let mut fixed = entity.clone();
+                    fixed.start_block = target;
+
+                    self.overwrite_entity_header(directory_block, &entity, &fixed)?;
+                }
+                Err(NoctFSError::NotFound) => {
+                    self.write_entity(directory_block, &Entity::directory(name, 0, target))?;
+                }
+                Err(e) => return Err(e),
+            },
+            Repair::RecoverChain {
+                start_block,
+                blocks,
+            } => {
+                let lost_and_found = self.mkdir_p(LOST_AND_FOUND)?.start_block;
+
+                let mut name = format!("chain-{start_block}");
+                let mut suffix = 0;
+
+                while self.find_entity(lost_and_found, &name).is_ok() {
+                    suffix += 1;
+                    name = format!("chain-{start_block}-{suffix}");
+                }
+
+                let size = blocks * self.block_size();
+
+                self.write_entity(lost_and_found, &Entity::file(name, size, start_block))?;
+            }
+            Repair::FreeBlock { block } => self.write_block(block, 0)?,
+            Repair::FixFreeMap { block, used } => self.mark_block(block, used)?,
+            Repair::FixLinkCount {
+                start_block,
+                ref directories,
+            } => self.set_links(start_block, directories.clone()),
+        }
+
+        Ok(())
+    }
+
+    fn check_tree(&mut self, walk: &mut Walk) -> Result<()> {
+        let root = self.get_root_entity()?;
+
+        match self.get_chain(root.start_block) {
+            Ok(chain) => {
+                walk.claim(&chain, "/");
+            }
+            Err(NoctFSError::Corrupted { block }) => {
+                walk.report.problems.push(Problem::BrokenChain {
+                    path: "/".to_string(),
+                    block,
+                });
+
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+
+        walk.report.directories += 1;
+
+        // (directory block, parent block, path)
+        let mut pending = vec![(root.start_block, root.start_block, "/".to_string())];
+
+        while let Some((directory_block, parent_block, path)) = pending.pop() {
+            let data = self.read_chain_data_vec(directory_block)?;
+            let (records, bad_offset) = parse_records(&data);
+
+            if let Some(offset) = bad_offset {
+                walk.report.problems.push(Problem::BadRecord {
+                    directory: path.clone(),
+                    offset,
+                });
+                walk.repairs.push(Repair::DropRecords {
+                    directory: path.clone(),
+                    directory_block,
+                    offset,
+                });
+            }
+
+            check_dot_entries(walk, &records, &path, directory_block, parent_block);
+
+            let mut names = BTreeSet::new();
+
+            for (_, entity) in records {
+                if entity.name == "." || entity.name == ".." {
+                    continue;
+                }
+
+                if !is_valid_name(&entity.name) {
+                    walk.report.problems.push(Problem::BadName {
+                        directory: path.clone(),
+                        name: entity.name.clone(),
+                    });
+                }
+
+                if !names.insert(entity.name.clone()) {
+                    walk.report.problems.push(Problem::DuplicateName {
+                        directory: path.clone(),
+                        name: entity.name.clone(),
+                    });
+                }
+
+                let entity_path = join_path(&path, &entity.name);
+
+                if let Some(directory) =
+                    self.check_entity(walk, &entity, &entity_path, directory_block)?
+                {
+                    pending.push(directory);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks one entity's chain. Returns the directory to descend into, if it's a healthy directory.
+    fn check_entity(
+        &mut self,
+        walk: &mut Walk,
+        entity: &Entity,
+        path: &str,
+        directory_block: BlockAddress,
+    ) -> Result<Option<(BlockAddress, BlockAddress, String)>> {
+        if entity.is_directory() {
+            walk.report.directories += 1;
+        } else if entity.is_symlink() {
+            walk.report.symlinks += 1;
+        } else {
+            walk.report.files += 1;
+        }
+
+        if walk.dangling.contains(&entity.start_block) {
+            walk.report.problems.push(Problem::BrokenChain {
+                path: path.to_string(),
+                block: entity.start_block,
+            });
+            walk.repairs.push(Repair::DropRecord {
+                path: path.to_string(),
+                directory_block,
+                name: entity.name.clone(),
+            });
+
+            return Ok(None);
+        }
+
+        if !entity.is_directory() {
+            // Another record of an already checked chain is a hard link, whether the link table knows it or not.
+            if let Some(directories) = walk.links.get_mut(&entity.start_block) {
+                directories.push(directory_block);
+
+                return Ok(None);
+            }
+
+            walk.links.insert(entity.start_block, vec![directory_block]);
+        }
+
+        let chain = match self.get_chain(entity.start_block) {
+            Ok(chain) => chain,
+            Err(NoctFSError::Corrupted { block }) => {
+                walk.report.problems.push(Problem::BrokenChain {
+                    path: path.to_string(),
+                    block,
+                });
+
+                self.check_broken_chain(walk, entity, path, directory_block)?;
+
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let exclusive = walk.claim(&chain, path);
+
+        if entity.is_directory() {
+            // A directory already claimed elsewhere would be walked twice, or forever.
+            return Ok(exclusive.then(|| (entity.start_block, directory_block, path.to_string())));
+        }
+
+        let expected_blocks = (entity.size.div_ceil(self.block_size() as u64) as usize).max(1);
+
+        if chain.len() != expected_blocks {
+            walk.report.problems.push(Problem::SizeMismatch {
+                path: path.to_string(),
+                size: entity.size,
+                blocks: chain.len(),
+            });
+
+            if chain.len() > expected_blocks {
+                walk.repairs.push(Repair::TruncateChain {
+                    path: path.to_string(),
+                    start_block: entity.start_block,
+                    blocks: expected_blocks,
+                });
+            } else {
+                walk.repairs.push(Repair::SetSize {
+                    path: path.to_string(),
+                    directory_block,
+                    name: entity.name.clone(),
+                    size: (chain.len() * self.block_size()) as u64,
+                });
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Plans the repair of a chain `get_chain` gave up on: the record goes if not even the first
+    /// block is there, otherwise the chain ends where it breaks and keeps the blocks before.
+    fn check_broken_chain(
+        &mut self,
+        walk: &mut Walk,
+        entity: &Entity,
+        path: &str,
+        directory_block: BlockAddress,
+    ) -> Result<()> {
+        let intact = self.intact_blocks(entity.start_block)?;
+
+        let Some(&last) = intact.last() else {
+            // Other records of the chain are dropped as well, so none of them counts as a link.
+            walk.links.remove(&entity.start_block);
+            walk.dangling.insert(entity.start_block);
+            walk.repairs.push(Repair::DropRecord {
+                path: path.to_string(),
+                directory_block,
+                name: entity.name.clone(),
+            });
+
+            return Ok(());
+        };
+
+        walk.claim(&intact, path);
+        walk.repairs.push(Repair::EndChain {
+            path: path.to_string(),
+            block: last,
+        });
+
+        // Records in the rest of the directory aren't visited.
+        if entity.is_directory() {
+            walk.incomplete = true;
+
+            return Ok(());
+        }
+
+        let size = (intact.len() * self.block_size()) as u64;
+
+        if entity.size > size {
+            walk.repairs.push(Repair::SetSize {
+                path: path.to_string(),
+                directory_block,
+                name: entity.name.clone(),
+                size,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Blocks of the chain up to where it links to a free or nonexistent block,
+    /// or back to one of its own blocks.
+    fn intact_blocks(&mut self, start_block: BlockAddress) -> Result<Vec<BlockAddress>> {
+        let mut blocks = vec![];
+        let mut visited = BTreeSet::new();
+        let mut current = start_block;
+
+        while visited.insert(current) {
+            match self.get_block(current)? {
+                Some(0) | None => break,
+                Some(next) => {
+                    blocks.push(current);
+
+                    if next == END_OF_CHAIN {
+                        break;
+                    }
+
+                    current = next;
+                }
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    /// Compares the links found in the tree with the link table.
+    fn check_link_counts(&self, walk: &mut Walk) {
+        let mut start_blocks: BTreeSet<BlockAddress> = self.link_table().keys().copied().collect();
+
+        start_blocks.extend(
+            walk.links
+                .iter()
+                .filter(|(_, directories)| directories.len() > 1)
+                .map(|(&start_block, _)| start_block),
+        );
+
+        for start_block in start_blocks {
+            let mut recorded = self
+                .link_table()
+                .get(&start_block)
+                .cloned()
+                .unwrap_or_default();
+            let mut found = walk.links.get(&start_block).cloned().unwrap_or_default();
+
+            recorded.sort_unstable();
+            found.sort_unstable();
+
+            if recorded == found {
+                continue;
+            }
+
+            walk.report.problems.push(Problem::LinkCountMismatch {
+                start_block,
+                recorded: recorded.len().max(1),
+                found: found.len(),
+            });
+            walk.repairs.push(Repair::FixLinkCount {
+                start_block,
+                directories: found,
+            });
+        }
+    }
+
+    /// Finds used blocks nobody owns, and disagreements between the block map and the bitmap.
+    fn check_block_map(&mut self, walk: &mut Walk) -> Result<()> {
+        let block_count = self.bootsector.block_map_count as u64;
+        let mut leaked = BTreeMap::new();
+
+        for block in 0..block_count {
+            let value = self.get_block(block)?.unwrap_or(0);
+            let used = value != 0;
+
+            if used {
+                walk.report.used_blocks += 1;
+            }
+
+            if used != self.free_map.is_used(block) {
+                walk.report
+                    .problems
+                    .push(Problem::FreeMapMismatch { block, used });
+                walk.repairs.push(Repair::FixFreeMap { block, used });
+            }
+
+            if used && !walk.owners.contains_key(&block) {
+                leaked.insert(block, value);
+            }
+        }
+
+        // Chain heads are leaked blocks no other leaked block links to.
+        let linked: BTreeSet<BlockAddress> = leaked.values().copied().collect();
+        let heads: Vec<BlockAddress> = leaked
+            .keys()
+            .copied()
+            .filter(|block| !linked.contains(block))
+            .collect();
+
+        for start_block in heads {
+            let mut blocks = vec![];
+            let mut current = start_block;
+            let mut terminated = false;
+
+            while let Some(next) = leaked.remove(&current) {
+                blocks.push(current);
+
+                if next == END_OF_CHAIN {
+                    terminated = true;
+                    break;
+                }
+
+                current = next;
+            }
+
+            walk.report.problems.push(Problem::LeakedChain {
+                start_block,
+                blocks: blocks.len(),
+            });
+
+            if walk.incomplete {
+                continue;
+            }
+
+            // Only a properly terminated chain can be read back as a file.
+            if terminated && !walk.discard_lost_chains {
+                walk.repairs.push(Repair::RecoverChain {
+                    start_block,
+                    blocks: blocks.len(),
+                });
+            } else {
+                walk.repairs
+                    .extend(blocks.into_iter().map(|block| Repair::FreeBlock { block }));
+            }
+        }
+
+        // Whatever is left forms loops without a head.
+        for block in leaked.into_keys() {
+            walk.report.problems.push(Problem::LeakedBlock { block });
+
+            if !walk.incomplete {
+                walk.repairs.push(Repair::FreeBlock { block });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_dot_entries(
+    walk: &mut Walk,
+    records: &[(usize, Entity)],
+    path: &str,
+    directory_block: BlockAddress,
+    parent_block: BlockAddress,
+) {
+    for (name, expected) in [(".", directory_block), ("..", parent_block)] {
+        let found = records
+            .iter()
+            .find(|(_, entity)| entity.name == name)
+            .map(|(_, entity)| entity.start_block);
+
+        if found != Some(expected) {
+            walk.report.problems.push(Problem::BadDotEntry {
+                directory: path.to_string(),
+                name,
+                expected,
+                found,
+            });
+            walk.repairs.push(Repair::RewriteDotEntry {
+                directory: path.to_string(),
+                directory_block,
+                name,
+                target: expected,
+            });
+        }
+    }
+}
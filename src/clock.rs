@@ -0,0 +1,46 @@
+use alloc::boxed::Box;
+
+/// Source of the current time for entity timestamps.
+///
+/// Time is counted in seconds since the Unix epoch, `0` meaning "unknown".
+/// Closures returning `u64` can be used as clocks too.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// Clock for targets without a time source. Every timestamp is left unknown.
+pub struct NullClock;
+
+impl Clock for NullClock {
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// Clock backed by `std::time::SystemTime`.
+#[cfg(feature = "std")]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+pub(crate) fn default_clock() -> Box<dyn Clock> {
+    #[cfg(feature = "std")]
+    return Box::new(SystemClock);
+
+    #[cfg(not(feature = "std"))]
+    return Box::new(NullClock);
+}
@@ -1,3 +1,64 @@
-use no_std_io::io::{Read, Seek, Write};
+use no_std_io::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{NoctFSError, Result};
 
 pub trait Device: Read + Seek + Write {}
+
+/// Device that may only be read from, e.g. write-protected media or a forensic image.
+///
+/// Implemented for everything that can read and seek, see [`NoctFS::mount_read_only`](crate::NoctFS::mount_read_only).
+pub trait ReadDevice: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadDevice for T {}
+
+/// Device a volume is mounted from. Read-only mounts can't write to it at all.
+pub(crate) enum DeviceHandle<'dev> {
+    ReadWrite(&'dev mut dyn Device),
+    ReadOnly(&'dev mut dyn ReadDevice),
+}
+
+impl DeviceHandle<'_> {
+    pub(crate) fn read_exact_at(&mut self, offset: u64, data: &mut [u8]) -> Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(data)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn write_all_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        match self {
+            Self::ReadWrite(device) => {
+                device.seek(SeekFrom::Start(offset))?;
+                device.write_all(data)?;
+
+                Ok(())
+            }
+            Self::ReadOnly(_) => Err(NoctFSError::ReadOnly),
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::ReadWrite(device) => Ok(device.flush()?),
+            Self::ReadOnly(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for DeviceHandle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::ReadWrite(device) => device.read(buf),
+            Self::ReadOnly(device) => device.read(buf),
+        }
+    }
+}
+
+impl Seek for DeviceHandle<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::ReadWrite(device) => device.seek(pos),
+            Self::ReadOnly(device) => device.seek(pos),
+        }
+    }
+}
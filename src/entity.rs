@@ -11,9 +11,44 @@ bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct EntityFlags: u32 {
         const DIRECTORY = (1 << 0);
+        /// Data chain holds the target path.
+        const SYMLINK = (1 << 1);
     }
 }
 
+/// Entity timestamps, in seconds since the Unix epoch. `0` means unknown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timestamps {
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+    pub changed: u64,
+}
+
+impl Timestamps {
+    /// Timestamps of an entity created at `time`.
+    pub fn at(time: u64) -> Self {
+        Self {
+            created: time,
+            modified: time,
+            accessed: time,
+            changed: time,
+        }
+    }
+}
+
+const TIMESTAMPS_SIZE: usize = 4 * 8;
+const OWNERSHIP_SIZE: usize = 3 * 4;
+
+/// Permission bits of newly created files.
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+/// Permission bits of newly created directories.
+pub const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+/// Permission bits of symbolic links, which aren't checked.
+pub const DEFAULT_SYMLINK_MODE: u32 = 0o777;
+/// Permission, set-id and sticky bits that can be stored in `Entity::mode`.
+pub const MODE_MASK: u32 = 0o7777;
+
 ///  [0..4]           (4 bytes) - Entity header size
 ///  [4..8]           (4 bytes) - Entity name length
 ///  [8..8+n]         (n bytes) - Entity name in UTF-8
@@ -21,6 +56,18 @@ bitflags! {
 ///  [8+n+8..8+n+16]  (8 bytes) - Data offset (block number)
 ///  [8+n+16..8+n+20] (4 bytes) - Flags
 ///  [8+n+20..8+n+24] (4 bytes) - Vendor data size
+///  [8+n+24..8+n+24+v]   (v bytes) - Vendor data
+///  [8+n+24+v..+8]       (8 bytes) - Creation time
+///  [8+n+24+v+8..+16]    (8 bytes) - Modification time
+///  [8+n+24+v+16..+24]   (8 bytes) - Access time
+///  [8+n+24+v+24..+32]   (8 bytes) - Change time
+///  [8+n+24+v+32..+36]   (4 bytes) - Owner user ID
+///  [8+n+24+v+36..+40]   (4 bytes) - Owner group ID
+///  [8+n+24+v+40..+44]   (4 bytes) - Permission bits
+///
+/// Older records end after the vendor data or after the timestamps.
+/// Readers tell them apart by the header size: missing timestamps are reported as unknown,
+/// and a missing owner and mode as root with the default permissions.
 
 #[derive(Debug, Clone)]
 pub struct Entity {
@@ -28,7 +75,12 @@ pub struct Entity {
     pub size: u64,
     pub start_block: BlockAddress,
     pub flags: EntityFlags,
-    pub vendor_data_size: u32,
+    /// Implementation-specific bytes stored with the record. NoctFS keeps extended attributes here.
+    pub vendor_data: Vec<u8>,
+    pub times: Timestamps,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
 }
 
 impl Entity {
@@ -38,7 +90,11 @@ impl Entity {
             size: size as _,
             start_block,
             flags: EntityFlags::empty(),
-            vendor_data_size: 0,
+            vendor_data: Vec::new(),
+            times: Timestamps::default(),
+            uid: 0,
+            gid: 0,
+            mode: DEFAULT_FILE_MODE,
         }
     }
 
@@ -48,13 +104,32 @@ impl Entity {
             size: size as _,
             start_block,
             flags: EntityFlags::DIRECTORY,
-            vendor_data_size: 0,
+            vendor_data: Vec::new(),
+            times: Timestamps::default(),
+            uid: 0,
+            gid: 0,
+            mode: DEFAULT_DIRECTORY_MODE,
+        }
+    }
+
+    pub fn symlink<T: ToString>(name: T, size: usize, start_block: BlockAddress) -> Self {
+        Self {
+            flags: EntityFlags::SYMLINK,
+            mode: DEFAULT_SYMLINK_MODE,
+            ..Self::file(name, size, start_block)
         }
     }
 
     // Header size field NOT included!
     pub fn header_size(&self) -> u32 {
-        (4 + self.name.len() + 8 + 8 + 4 + 4 + self.vendor_data_size as usize) as u32
+        (4 + self.name.len()
+            + 8
+            + 8
+            + 4
+            + 4
+            + self.vendor_data.len()
+            + TIMESTAMPS_SIZE
+            + OWNERSHIP_SIZE) as u32
     }
 
     pub fn fact_size(&self) -> u32 {
@@ -70,7 +145,7 @@ impl Entity {
         let r_size = self.size.to_le_bytes();
         let r_offset = self.start_block.to_le_bytes();
         let r_flags = self.flags.bits().to_le_bytes();
-        let r_vendor_data_size = self.vendor_data_size.to_le_bytes();
+        let r_vendor_data_size = (self.vendor_data.len() as u32).to_le_bytes();
 
         data.extend_from_slice(&r_header_size);
         data.extend_from_slice(&r_namesize);
@@ -79,44 +154,102 @@ impl Entity {
         data.extend_from_slice(&r_offset);
         data.extend_from_slice(&r_flags);
         data.extend_from_slice(&r_vendor_data_size);
+        data.extend_from_slice(&self.vendor_data);
+
+        for time in [
+            self.times.created,
+            self.times.modified,
+            self.times.accessed,
+            self.times.changed,
+        ] {
+            data.extend_from_slice(&time.to_le_bytes());
+        }
+
+        for field in [self.uid, self.gid, self.mode] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
 
         data.into_boxed_slice()
     }
 
-    pub fn from_raw(data: &[u8]) -> Self {
-        let (_, rest) = data.split_at(4); // Skip entity header size
-        let (namesize_bytes, rest) = rest.split_at(4);
+    pub fn from_raw(data: &[u8]) -> Option<Self> {
+        let (header_size_bytes, rest) = data.split_at_checked(4)?;
 
-        let namesize = u32::from_le_bytes(namesize_bytes.try_into().unwrap()) as usize;
+        // Don't look past the end of this record.
+        let header_size = u32::from_le_bytes(header_size_bytes.try_into().ok()?) as usize;
+        let rest = rest.get(..header_size).unwrap_or(rest);
 
-        let (name, rest) = rest.split_at(namesize);
-        let name = String::from_utf8_lossy(name).into_owned();
+        let (namesize_bytes, rest) = rest.split_at_checked(4)?;
 
-        let (size_bytes, rest) = rest.split_at(8);
-        let (offset_bytes, rest) = rest.split_at(BLOCK_ADDRESS_SIZE);
-        let (flags_bytes, rest) = rest.split_at(4);
-        let (vendor_data_size_bytes, _) = rest.split_at(4);
+        let namesize = u32::from_le_bytes(namesize_bytes.try_into().ok()?) as usize;
 
-        let size = u64::from_le_bytes(size_bytes.try_into().unwrap());
-        let offset = u64::from_le_bytes(offset_bytes.try_into().unwrap());
-        let flags =
-            EntityFlags::from_bits(u32::from_le_bytes(flags_bytes.try_into().unwrap())).unwrap();
-        let vendor_data_size = u32::from_le_bytes(vendor_data_size_bytes.try_into().unwrap());
+        let (name, rest) = rest.split_at_checked(namesize)?;
+        let name = String::from_utf8_lossy(name).into_owned();
 
-        Self {
+        let (size_bytes, rest) = rest.split_at_checked(8)?;
+        let (offset_bytes, rest) = rest.split_at_checked(BLOCK_ADDRESS_SIZE)?;
+        let (flags_bytes, rest) = rest.split_at_checked(4)?;
+        let (vendor_data_size_bytes, rest) = rest.split_at_checked(4)?;
+
+        let size = u64::from_le_bytes(size_bytes.try_into().ok()?);
+        let offset = u64::from_le_bytes(offset_bytes.try_into().ok()?);
+        let flags = EntityFlags::from_bits(u32::from_le_bytes(flags_bytes.try_into().ok()?))?;
+        let vendor_data_size = u32::from_le_bytes(vendor_data_size_bytes.try_into().ok()?);
+
+        let (vendor_data, extension) = rest.split_at_checked(vendor_data_size as usize)?;
+
+        let times = match extension.get(..TIMESTAMPS_SIZE) {
+            Some(raw) => {
+                let time =
+                    |nr: usize| u64::from_le_bytes(raw[nr * 8..nr * 8 + 8].try_into().unwrap());
+
+                Timestamps {
+                    created: time(0),
+                    modified: time(1),
+                    accessed: time(2),
+                    changed: time(3),
+                }
+            }
+            None => Timestamps::default(),
+        };
+
+        let (uid, gid, mode) =
+            match extension.get(TIMESTAMPS_SIZE..TIMESTAMPS_SIZE + OWNERSHIP_SIZE) {
+                Some(raw) => {
+                    let field =
+                        |nr: usize| u32::from_le_bytes(raw[nr * 4..nr * 4 + 4].try_into().unwrap());
+
+                    (field(0), field(1), field(2) & MODE_MASK)
+                }
+                None if flags.contains(EntityFlags::DIRECTORY) => (0, 0, DEFAULT_DIRECTORY_MODE),
+                None if flags.contains(EntityFlags::SYMLINK) => (0, 0, DEFAULT_SYMLINK_MODE),
+                None => (0, 0, DEFAULT_FILE_MODE),
+            };
+
+        Some(Self {
             name,
             size,
             start_block: offset,
             flags,
-            vendor_data_size,
-        }
+            vendor_data: vendor_data.to_vec(),
+            times,
+            uid,
+            gid,
+            mode,
+        })
     }
 
     pub fn is_file(&self) -> bool {
-        !self.flags.contains(EntityFlags::DIRECTORY)
+        !self
+            .flags
+            .intersects(EntityFlags::DIRECTORY | EntityFlags::SYMLINK)
     }
 
     pub fn is_directory(&self) -> bool {
         self.flags.contains(EntityFlags::DIRECTORY)
     }
+
+    pub fn is_symlink(&self) -> bool {
+        self.flags.contains(EntityFlags::SYMLINK)
+    }
 }
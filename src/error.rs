@@ -0,0 +1,169 @@
+use core::fmt;
+
+use no_std_io::io::{Error, ErrorKind};
+
+use crate::BlockAddress;
+
+pub type Result<T> = core::result::Result<T, NoctFSError>;
+
+#[derive(Debug)]
+pub enum NoctFSError {
+    /// Bootsector does not carry the NoctFS codename.
+    SignatureNotValid,
+    /// Bootsector carries the codename, but `field` is out of range.
+    InvalidBootSector { field: &'static str },
+    /// Volume was formatted by a newer driver with an incompatible layout.
+    UnsupportedVersion { version: u16 },
+    /// Volume uses features this driver doesn't know. Bits are the unknown ones.
+    UnsupportedFeatures { incompat: u32, ro_compat: u32 },
+    /// Volume label is too long or contains a NUL character.
+    InvalidLabel,
+    /// Volume is mounted read-only.
+    ReadOnly,
+    /// Volume wasn't unmounted cleanly and should be checked before it's written to.
+    DirtyVolume,
+    /// Volume has no journal, which the operation needs.
+    NoJournal,
+    /// No free blocks left on the volume.
+    NoSpace,
+    /// Requested entity does not exist.
+    NotFound,
+    /// An entity with the same name already exists in the directory.
+    AlreadyExists,
+    /// Entity was expected to be a directory, but it's not.
+    NotADirectory,
+    /// Entity was expected to be a file, but it's a directory.
+    IsADirectory,
+    /// On-disk structures are inconsistent around `block`.
+    Corrupted { block: BlockAddress },
+    /// Entity name is empty, too long or contains forbidden characters.
+    InvalidName,
+    /// Directory still has entries other than `.` and `..`.
+    DirectoryNotEmpty,
+    /// A directory can't be moved into itself or one of its descendants.
+    InvalidMove,
+    /// Caller's credentials don't allow the operation.
+    PermissionDenied,
+    /// Value exceeds a size limit, e.g. of extended attributes or of the journal.
+    TooLarge,
+    /// Entity was expected to be a symbolic link, but it's not.
+    NotASymlink,
+    /// Too many symbolic links were followed while resolving a path.
+    SymlinkLoop,
+    /// Underlying device failed.
+    Io(Error),
+}
+
+impl fmt::Display for NoctFSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBootSector { field } => write!(f, "{}: {field}", self.summary()),
+            Self::UnsupportedVersion { version } => write!(f, "{} {version}", self.summary()),
+            Self::UnsupportedFeatures {
+                incompat,
+                ro_compat,
+            } => write!(
+                f,
+                "{}: incompatible {incompat:#x}, read-only compatible {ro_compat:#x}",
+                self.summary()
+            ),
+            Self::Corrupted { block } => write!(f, "{} at block {block}", self.summary()),
+            Self::Io(e) => write!(f, "{}: {e}", self.summary()),
+            _ => f.write_str(self.summary()),
+        }
+    }
+}
+
+impl core::error::Error for NoctFSError {}
+
+impl From<Error> for NoctFSError {
+    fn from(value: Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl NoctFSError {
+    fn summary(&self) -> &'static str {
+        match self {
+            Self::SignatureNotValid => "not a NoctFS volume",
+            Self::InvalidBootSector { .. } => "invalid bootsector",
+            Self::UnsupportedVersion { .. } => "unsupported format version",
+            Self::UnsupportedFeatures { .. } => "unsupported filesystem features",
+            Self::InvalidLabel => "invalid volume label",
+            Self::ReadOnly => "volume is mounted read-only",
+            Self::DirtyVolume => "volume wasn't unmounted cleanly",
+            Self::NoJournal => "volume has no journal",
+            Self::NoSpace => "no space left on volume",
+            Self::NotFound => "entity not found",
+            Self::AlreadyExists => "entity already exists",
+            Self::NotADirectory => "not a directory",
+            Self::IsADirectory => "is a directory",
+            Self::Corrupted { .. } => "filesystem corrupted",
+            Self::InvalidName => "invalid entity name",
+            Self::DirectoryNotEmpty => "directory not empty",
+            Self::InvalidMove => "can't move a directory into itself",
+            Self::PermissionDenied => "permission denied",
+            Self::TooLarge => "value too large",
+            Self::NotASymlink => "not a symbolic link",
+            Self::SymlinkLoop => "too many levels of symbolic links",
+            Self::Io(_) => "I/O error",
+        }
+    }
+}
+
+/// Lets NoctFS errors travel through `Read`/`Write`/`Seek` implementations.
+impl From<NoctFSError> for Error {
+    fn from(value: NoctFSError) -> Self {
+        let kind = match value {
+            NoctFSError::Io(e) => return e,
+            NoctFSError::NotFound => ErrorKind::NotFound,
+            NoctFSError::AlreadyExists => ErrorKind::AlreadyExists,
+            NoctFSError::PermissionDenied | NoctFSError::ReadOnly => ErrorKind::PermissionDenied,
+            NoctFSError::InvalidName
+            | NoctFSError::InvalidLabel
+            | NoctFSError::InvalidMove
+            | NoctFSError::TooLarge
+            | NoctFSError::NotASymlink => ErrorKind::InvalidInput,
+            NoctFSError::SignatureNotValid
+            | NoctFSError::InvalidBootSector { .. }
+            | NoctFSError::Corrupted { .. } => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        };
+
+        Error::new(kind, value.summary())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<NoctFSError> for std::io::Error {
+    fn from(value: NoctFSError) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match value {
+            NoctFSError::NotFound => ErrorKind::NotFound,
+            NoctFSError::AlreadyExists => ErrorKind::AlreadyExists,
+            NoctFSError::PermissionDenied => ErrorKind::PermissionDenied,
+            NoctFSError::ReadOnly => ErrorKind::ReadOnlyFilesystem,
+            NoctFSError::NoSpace => ErrorKind::StorageFull,
+            NoctFSError::NotADirectory => ErrorKind::NotADirectory,
+            NoctFSError::IsADirectory => ErrorKind::IsADirectory,
+            NoctFSError::InvalidName
+            | NoctFSError::InvalidLabel
+            | NoctFSError::InvalidMove
+            | NoctFSError::TooLarge
+            | NoctFSError::NotASymlink => ErrorKind::InvalidInput,
+            NoctFSError::DirectoryNotEmpty => ErrorKind::DirectoryNotEmpty,
+            NoctFSError::SignatureNotValid
+            | NoctFSError::InvalidBootSector { .. }
+            | NoctFSError::Corrupted { .. } => ErrorKind::InvalidData,
+            NoctFSError::UnsupportedVersion { .. }
+            | NoctFSError::UnsupportedFeatures { .. }
+            | NoctFSError::NoJournal => ErrorKind::Unsupported,
+            NoctFSError::SymlinkLoop | NoctFSError::DirtyVolume | NoctFSError::Io(_) => {
+                ErrorKind::Other
+            }
+        };
+
+        std::io::Error::new(kind, value)
+    }
+}
@@ -0,0 +1,220 @@
+use no_std_io::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{access::Access, entity::Entity, BlockAddress, NoctFS, NoctFSError, Result};
+
+/// Seconds after which a read updates the access time again.
+const ACCESS_TIME_PERIOD: u64 = 24 * 60 * 60;
+
+/// An open file with its own cursor.
+///
+/// The handle remembers the directory holding the file's record, so reads and writes
+/// don't need it to be passed around, and keeps a copy of the entity that is updated on every write.
+pub struct File<'fs, 'dev> {
+    fs: &'fs mut NoctFS<'dev>,
+    directory_block: BlockAddress,
+    entity: Entity,
+    position: u64,
+}
+
+impl<'dev> NoctFS<'dev> {
+    /// Opens the file at `path`.
+    pub fn open(&mut self, path: &str) -> Result<File<'_, 'dev>> {
+        let (directory_block, entity) = self.open_path(path)?;
+
+        self.open_entity(directory_block, entity)
+    }
+
+    /// Opens a file from its record in the directory at `directory_block`.
+    ///
+    /// A symbolic link is followed to its target.
+    /// With credentials set, opening needs read permission; writes check for write permission.
+    pub fn open_entity(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: Entity,
+    ) -> Result<File<'_, 'dev>> {
+        // Same as in `read_contents_by_entity`: the record has the final word.
+        let entity = self.current_record(directory_block, &entity)?;
+
+        let (directory_block, entity) = match entity.is_symlink() {
+            true => self.follow_link(directory_block, &entity)?,
+            false => (directory_block, entity),
+        };
+
+        if entity.is_directory() {
+            return Err(NoctFSError::IsADirectory);
+        }
+
+        self.check_access(&entity, Access::READ)?;
+
+        Ok(File {
+            fs: self,
+            directory_block,
+            entity,
+            position: 0,
+        })
+    }
+}
+
+impl File<'_, '_> {
+    pub fn entity(&self) -> &Entity {
+        &self.entity
+    }
+
+    pub fn directory_block(&self) -> BlockAddress {
+        self.directory_block
+    }
+
+    pub fn len(&self) -> u64 {
+        self.entity.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entity.size == 0
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Truncates or extends the file to `len` bytes. The cursor is left where it was.
+    pub fn set_len(&mut self, len: u64) -> Result<()> {
+        self.entity = self.fs.truncate(self.directory_block, &self.entity, len)?;
+
+        Ok(())
+    }
+
+    fn read_inner(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining = self.entity.size.saturating_sub(self.position);
+        let count = core::cmp::min(buf.len() as u64, remaining) as usize;
+
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let read = self.fs.read_contents_by_entity(
+            self.directory_block,
+            &self.entity,
+            &mut buf[..count],
+            self.position,
+        )?;
+
+        self.position += read as u64;
+
+        self.update_access_time()?;
+
+        Ok(read)
+    }
+
+    /// Records the access like `relatime` does: only when the access time is older than
+    /// the last modification or a day old, so reading doesn't rewrite the directory every time.
+    fn update_access_time(&mut self) -> Result<()> {
+        let options = self.fs.mount_options();
+
+        if options.read_only || options.no_atime {
+            return Ok(());
+        }
+
+        let now = self.fs.clock.now();
+        let times = self.entity.times;
+
+        if now == 0
+            || (times.accessed > times.modified && now < times.accessed + ACCESS_TIME_PERIOD)
+        {
+            return Ok(());
+        }
+
+        self.entity = self.fs.mark_accessed(self.directory_block, &self.entity)?;
+
+        Ok(())
+    }
+
+    fn write_inner(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let written = self.fs.write_contents_by_entity(
+            self.directory_block,
+            &self.entity,
+            buf,
+            self.position,
+        )?;
+
+        self.position += written as u64;
+        self.entity.size = core::cmp::max(self.entity.size, self.position);
+
+        Ok(written)
+    }
+
+    fn seek_inner(&mut self, pos: SeekFrom) -> Option<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.entity.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }?;
+
+        self.position = position;
+
+        Some(position)
+    }
+}
+
+impl Read for File<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.read_inner(buf)?)
+    }
+}
+
+impl Write for File<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(self.write_inner(buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.fs.sync()?)
+    }
+}
+
+impl Seek for File<'_, '_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.seek_inner(pos).ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        ))
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for File<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(self.read_inner(buf)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for File<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(self.write_inner(buf)?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(self.fs.sync()?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Seek for File<'_, '_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            std::io::SeekFrom::Start(offset) => SeekFrom::Start(offset),
+            std::io::SeekFrom::End(offset) => SeekFrom::End(offset),
+            std::io::SeekFrom::Current(offset) => SeekFrom::Current(offset),
+        };
+
+        self.seek_inner(pos).ok_or(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        ))
+    }
+}
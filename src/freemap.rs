@@ -0,0 +1,118 @@
+use alloc::{vec, vec::Vec};
+
+use crate::BlockAddress;
+
+/// In-memory copy of the free block bitmap.
+///
+/// Bit `n` is set when block `n` is in use. Next-fit search starts at `hint`,
+/// so consecutive allocations don't rescan the beginning of the volume.
+pub(crate) struct FreeMap {
+    words: Vec<u64>,
+    block_count: u64,
+    free: u64,
+    hint: usize,
+}
+
+impl FreeMap {
+    /// Creates a bitmap with every block marked as free.
+    pub(crate) fn new(block_count: u64) -> Self {
+        Self {
+            words: vec![0; block_count.div_ceil(64) as usize],
+            block_count,
+            free: block_count,
+            hint: 0,
+        }
+    }
+
+    /// Builds a bitmap from its on-disk representation.
+    pub(crate) fn from_bytes(block_count: u64, data: &[u8]) -> Self {
+        let mut map = Self::new(block_count);
+
+        for (nr, word) in map.words.iter_mut().enumerate() {
+            let mut raw = [0u8; 8];
+            let start = (nr * 8).min(data.len());
+            let end = (nr * 8 + 8).min(data.len());
+
+            raw[..end - start].copy_from_slice(&data[start..end]);
+
+            *word = u64::from_le_bytes(raw);
+        }
+
+        // Bits past the end of the volume must never be handed out.
+        let tail = block_count % 64;
+
+        if let (Some(last), true) = (map.words.last_mut(), tail != 0) {
+            *last &= (1 << tail) - 1;
+        }
+
+        let used: u64 = map.words.iter().map(|w| w.count_ones() as u64).sum();
+        map.free = block_count - used;
+
+        map
+    }
+
+    /// Size of the on-disk representation in bytes.
+    pub(crate) fn byte_len(block_count: u64) -> usize {
+        block_count.div_ceil(8) as usize
+    }
+
+    /// Byte at `index` of the on-disk representation.
+    pub(crate) fn byte(&self, index: usize) -> u8 {
+        self.words[index / 8].to_le_bytes()[index % 8]
+    }
+
+    pub(crate) fn free_count(&self) -> u64 {
+        self.free
+    }
+
+    pub(crate) fn is_used(&self, block: BlockAddress) -> bool {
+        self.words[(block / 64) as usize] & (1 << (block % 64)) != 0
+    }
+
+    /// Marks the block as used or free. Returns `true` if the bit actually changed.
+    pub(crate) fn set(&mut self, block: BlockAddress, used: bool) -> bool {
+        if block >= self.block_count || self.is_used(block) == used {
+            return false;
+        }
+
+        let word = (block / 64) as usize;
+
+        self.words[word] ^= 1 << (block % 64);
+
+        if used {
+            self.free -= 1;
+        } else {
+            self.free += 1;
+            self.hint = self.hint.min(word);
+        }
+
+        true
+    }
+
+    /// Finds a free block, without marking it.
+    pub(crate) fn find_free(&mut self) -> Option<BlockAddress> {
+        if self.free == 0 {
+            return None;
+        }
+
+        let words = self.words.len();
+
+        for nr in (self.hint..words).chain(0..self.hint) {
+            let word = self.words[nr];
+
+            if word != u64::MAX {
+                let block = nr as u64 * 64 + word.trailing_ones() as u64;
+
+                if block >= self.block_count {
+                    continue;
+                }
+
+                self.hint = nr;
+
+                return Some(block);
+            }
+        }
+
+        None
+    }
+}
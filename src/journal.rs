@@ -0,0 +1,599 @@
+//! Metadata journal.
+//!
+//! Operations changing several pieces of metadata at once (block map entries, the free block
+//! bitmap, the link table and directory records) run as transactions. Their writes are held in
+//! memory until the operation is done, then written to the journal chain, and only then to
+//! their places on the volume. A crash leaves either a complete transaction in the journal,
+//! which is replayed on the next mount, or nothing, so the volume never ends up half-changed.
+//! File contents aren't journaled, but they're written before the transaction that makes them
+//! reachable is committed. Transactions started by [`NoctFS::transaction`] are the exception:
+//! they may be rolled back, so they hold back file contents as well.
+//!
+//! The journal chain starts with a header:
+//!  [0..8]   (8 bytes) - Magic, "NoctJrnl", or zeroes if the journal is empty
+//!  [8..12]  (4 bytes) - State, see `STATE_*`
+//!  [12..16] (4 bytes) - Number of records
+//!  [16..24] (8 bytes) - Size of the records, in bytes
+//!  [24..32] (8 bytes) - FNV-1a hash of the records
+//!
+//! followed by the records, each being:
+//!  [0..8]  (8 bytes) - Device offset of the data
+//!  [8..12] (4 bytes) - Data size
+//!  [12..]            - Data
+
+use alloc::{vec, vec::Vec};
+use no_std_io::io::{Seek, SeekFrom};
+
+use crate::{
+    bootsector::{BootSector, CompatFeatures, BOOTSECTOR_SIZE},
+    cache::BlockMapCache,
+    BlockAddress, NoctFS, NoctFSError, Result,
+};
+
+const MAGIC: &[u8; 8] = b"NoctJrnl";
+const HEADER_SIZE: usize = 32;
+const RECORD_HEADER_SIZE: usize = 12;
+
+/// The records hold a complete transaction that may not be in place yet.
+const STATE_COMMITTED: u32 = 1;
+/// A transaction too large for the journal is being written in place, without a copy.
+const STATE_IN_PLACE: u32 = 2;
+
+/// Smallest and largest journal `format` creates by default.
+const MIN_DEFAULT_SIZE: usize = 128 * 1024;
+const MAX_DEFAULT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Journal size for a volume of `device_size` bytes, unless told otherwise.
+pub(crate) fn default_size(device_size: usize) -> usize {
+    core::cmp::min(
+        (device_size / 32).clamp(MIN_DEFAULT_SIZE, MAX_DEFAULT_SIZE),
+        device_size / 8,
+    )
+}
+
+pub(crate) struct Journal {
+    /// Blocks of the journal chain.
+    chain: Vec<BlockAddress>,
+    /// Whether writes are held back for the transaction.
+    in_transaction: bool,
+    /// File contents being written during a transaction, which go straight to the device.
+    writing_contents: bool,
+    /// Whether file contents are held back too, see [`NoctFS::transaction`].
+    journal_contents: bool,
+    /// Size the held-back writes take in the journal.
+    held_size: usize,
+    /// Writes that haven't reached their place on the device yet: those of the open
+    /// transaction or, on a read-only mount, those of a transaction left by a crash.
+    overlay: Vec<(u64, Vec<u8>)>,
+}
+
+impl Journal {
+    fn new(chain: Vec<BlockAddress>) -> Self {
+        Self {
+            chain,
+            in_transaction: false,
+            writing_contents: false,
+            journal_contents: false,
+            held_size: 0,
+            overlay: vec![],
+        }
+    }
+
+    /// Brings held-back writes overlapping `data` at `offset` up to date with it.
+    /// Returns `true` if one of them covers exactly the same range.
+    fn patch(&mut self, offset: u64, data: &[u8]) -> bool {
+        let mut exact = false;
+
+        for (held_offset, held) in &mut self.overlay {
+            copy_overlap(offset, data, *held_offset, held);
+
+            exact |= *held_offset == offset && held.len() == data.len();
+        }
+
+        exact
+    }
+
+    /// Lays held-back writes over `data`, just read from the device at `offset`.
+    fn apply(&self, offset: u64, data: &mut [u8]) {
+        for (held_offset, held) in &self.overlay {
+            copy_overlap(*held_offset, held, offset, data);
+        }
+    }
+}
+
+/// Copies the bytes `src` at `src_offset` has in common with `dst` at `dst_offset`.
+fn copy_overlap(src_offset: u64, src: &[u8], dst_offset: u64, dst: &mut [u8]) {
+    let start = core::cmp::max(src_offset, dst_offset);
+    let end = core::cmp::min(src_offset + src.len() as u64, dst_offset + dst.len() as u64);
+
+    if start >= end {
+        return;
+    }
+
+    dst[(start - dst_offset) as usize..(end - dst_offset) as usize]
+        .copy_from_slice(&src[(start - src_offset) as usize..(end - src_offset) as usize]);
+}
+
+/// Tells a transaction that made it to the journal from one cut short by a crash.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+fn encode_records(writes: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut raw = vec![];
+
+    for (offset, data) in writes {
+        raw.extend_from_slice(&offset.to_le_bytes());
+        raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        raw.extend_from_slice(data);
+    }
+
+    raw
+}
+
+fn decode_records(raw: &[u8], count: usize) -> Option<Vec<(u64, Vec<u8>)>> {
+    let mut writes = vec![];
+    let mut index = 0;
+
+    for _ in 0..count {
+        let header = raw.get(index..index + RECORD_HEADER_SIZE)?;
+        let offset = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let size = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+
+        index += RECORD_HEADER_SIZE;
+
+        writes.push((offset, raw.get(index..index + size)?.to_vec()));
+
+        index += size;
+    }
+
+    Some(writes)
+}
+
+impl NoctFS<'_> {
+    /// Allocates a journal of at least `size` bytes and records it in the bootsector.
+    pub(crate) fn create_journal(&mut self, size: usize) -> Result<()> {
+        let start_block = self.allocate_bytes(core::cmp::max(size, HEADER_SIZE))?;
+        let chain = self.get_chain(start_block)?.into_vec();
+
+        let zeroes = vec![0u8; chain.len() * self.block_size()];
+        self.write_blocks_data(start_block, &zeroes, 0)?;
+
+        self.bootsector.journal_block = start_block;
+        self.bootsector
+            .compat_features
+            .insert(CompatFeatures::JOURNAL);
+        self.write_bootsector()?;
+
+        self.journal = Some(Journal::new(chain));
+
+        Ok(())
+    }
+
+    /// Replays the transaction a crash left in the journal, if there is one.
+    /// A read-only mount keeps it in memory instead.
+    ///
+    /// Returns `false` if the volume may be inconsistent regardless: it has no journal,
+    /// a transaction too large for the journal was being written in place, or, on a read-only
+    /// mount, the journal is damaged.
+    pub(crate) fn load_journal(&mut self) -> Result<bool> {
+        let journal_block = self.bootsector.journal_block;
+
+        if journal_block == 0 {
+            return Ok(false);
+        }
+
+        let chain = self.get_chain(journal_block)?.into_vec();
+        let capacity = chain.len() * self.block_size();
+
+        self.journal = Some(Journal::new(chain));
+
+        let mut header = [0u8; HEADER_SIZE];
+        self.read_journal_area(0, &mut header)?;
+
+        if &header[0..8] != MAGIC {
+            return Ok(true);
+        }
+
+        let state = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let count = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        let size = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+        let hash = u64::from_le_bytes(header[24..32].try_into().unwrap());
+
+        // A damaged journal can't be replayed, but a read-only mount still lets the checker run.
+        let damaged = match self.options.read_only {
+            true => Ok(false),
+            false => Err(NoctFSError::Corrupted {
+                block: journal_block,
+            }),
+        };
+
+        match state {
+            STATE_COMMITTED if size <= capacity - HEADER_SIZE => {}
+            STATE_IN_PLACE => return Ok(false),
+            _ => return damaged,
+        }
+
+        let mut records = vec![0u8; size];
+        self.read_journal_area(HEADER_SIZE, &mut records)?;
+
+        // The header didn't make it to the device whole, so nothing was written in place yet.
+        if checksum(&records) != hash {
+            if !self.options.read_only {
+                self.clear_journal()?;
+            }
+
+            return Ok(true);
+        }
+
+        let Some(writes) = decode_records(&records, count) else {
+            return damaged;
+        };
+
+        if self.options.read_only {
+            if let Some(journal) = &mut self.journal {
+                journal.overlay = writes;
+            }
+        } else {
+            for (offset, data) in &writes {
+                self.device.write_all_at(*offset, data)?;
+            }
+
+            self.device.flush()?;
+            self.clear_journal()?;
+        }
+
+        // The transaction may have changed both of these.
+        self.reload_bootsector()?;
+        self.cache = BlockMapCache::new(self.options.cache_size);
+
+        Ok(true)
+    }
+
+    fn reload_bootsector(&mut self) -> Result<()> {
+        let mut raw = [0u8; BOOTSECTOR_SIZE];
+        self.read_at(0, &mut raw)?;
+
+        let device_size = self.device.seek(SeekFrom::End(0))?;
+        let bootsector = BootSector::from_raw(&raw)?;
+
+        bootsector.validate(device_size)?;
+
+        self.bootsector = bootsector;
+
+        Ok(())
+    }
+
+    /// Runs `op` as a single all-or-nothing unit.
+    ///
+    /// `op` gets the filesystem itself, so every operation is available inside. If it fails,
+    /// none of its changes are made. Otherwise they're committed together, and a crash leaves
+    /// either all of them or none.
+    ///
+    /// Unlike single operations, a transaction journals file contents as well, so everything
+    /// written in it has to fit in the journal. A larger one fails with `TooLarge`
+    /// and is rolled back. Volumes without a journal fail with `NoJournal`.
+    ///
+    /// A transaction started inside another one becomes part of it. If the inner one fails,
+    /// only its own changes are undone, and the outer one may go on.
+    pub fn transaction<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.check_writable()?;
+
+        match &self.journal {
+            Some(journal) if journal.in_transaction => return self.nested_transaction(op),
+            Some(_) => {}
+            None => return Err(NoctFSError::NoJournal),
+        }
+
+        // Rolling back reloads metadata from the device, so nothing may be left in memory only.
+        self.sync()?;
+
+        if let Some(journal) = &mut self.journal {
+            journal.in_transaction = true;
+            journal.journal_contents = true;
+        }
+
+        match op(self) {
+            Ok(value) => self.commit_or_roll_back().map(|_| value),
+            Err(e) => {
+                self.roll_back()?;
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs `op` inside the open transaction, going back to where it started if `op` fails.
+    fn nested_transaction<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        // Cached metadata joins the held-back writes, so the savepoint covers it as well.
+        self.write_back_metadata()?;
+
+        let savepoint = match &self.journal {
+            Some(journal) => (journal.overlay.clone(), journal.held_size),
+            None => return op(self),
+        };
+
+        let result = op(self);
+
+        if result.is_err() {
+            if let Some(journal) = &mut self.journal {
+                (journal.overlay, journal.held_size) = savepoint;
+            }
+
+            self.reload_metadata()?;
+        }
+
+        result
+    }
+
+    /// Drops the writes held back by the open transaction, along with every in-memory change.
+    ///
+    /// A transaction that made it to the journal before its commit failed is replayed instead.
+    fn roll_back(&mut self) -> Result<()> {
+        if let Some(journal) = &mut self.journal {
+            journal.in_transaction = false;
+            journal.journal_contents = false;
+            journal.held_size = 0;
+            journal.overlay.clear();
+        }
+
+        self.cache = BlockMapCache::new(self.options.cache_size);
+
+        // Only fails to be consistent if the commit was cut short while writing in place.
+        if !self.load_journal()? {
+            self.needs_check = true;
+        }
+
+        self.reload_metadata()
+    }
+
+    /// Reloads everything kept in memory from the device, as seen through held-back writes.
+    fn reload_metadata(&mut self) -> Result<()> {
+        self.reload_bootsector()?;
+        self.cache = BlockMapCache::new(self.options.cache_size);
+
+        self.free_map_chain.clear();
+        self.free_map_dirty.clear();
+        self.load_free_map()?;
+
+        self.links.clear();
+        self.links_dirty = false;
+        self.load_link_table()?;
+
+        self.entity_counts = None;
+
+        Ok(())
+    }
+
+    /// Runs `op` as a single transaction. If `op` or the commit fails, none of its changes
+    /// are made, on the device or in memory.
+    ///
+    /// Inside a transaction started by [`NoctFS::transaction`], `op` is undone on its own if it
+    /// fails, like a nested transaction. Inside another atomic operation, it simply becomes part
+    /// of it. Without a journal, `op` writes to the device as it goes, and may fail halfway.
+    pub(crate) fn atomic<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        match &self.journal {
+            Some(journal) if journal.journal_contents => return self.nested_transaction(op),
+            Some(journal) if !journal.in_transaction && !self.options.read_only => {}
+            _ => return op(self),
+        }
+
+        // Rolling back reloads metadata from the device, so changes made outside of any
+        // transaction, e.g. by `allocate_blocks`, are committed on their own first.
+        if self.has_dirty_metadata() {
+            self.begin_transaction();
+            self.commit_or_roll_back()?;
+        }
+
+        self.begin_transaction();
+
+        let result = op(self);
+
+        match result {
+            Ok(value) => self.commit_or_roll_back().map(|_| value),
+            Err(e) => {
+                // Nothing to undo, and reloading the free block bitmap isn't cheap.
+                if self.has_dirty_metadata() || self.has_held_writes() {
+                    self.roll_back()?;
+                } else {
+                    self.end_transaction();
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    fn begin_transaction(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.in_transaction = true;
+        }
+    }
+
+    /// Closes a transaction that held back nothing.
+    fn end_transaction(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.in_transaction = false;
+        }
+    }
+
+    fn has_held_writes(&self) -> bool {
+        self.journal
+            .as_ref()
+            .is_some_and(|journal| !journal.overlay.is_empty())
+    }
+
+    fn commit_or_roll_back(&mut self) -> Result<()> {
+        let committed = self.commit();
+
+        if committed.is_err() {
+            self.roll_back()?;
+        }
+
+        committed
+    }
+
+    /// Writes file contents, which skip the journal even inside a transaction.
+    pub(crate) fn write_contents(
+        &mut self,
+        start_block: BlockAddress,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<usize> {
+        if let Some(journal) = &mut self.journal {
+            journal.writing_contents = true;
+        }
+
+        let result = self.write_blocks_data(start_block, data, offset);
+
+        if let Some(journal) = &mut self.journal {
+            journal.writing_contents = false;
+        }
+
+        result
+    }
+
+    /// Holds a write back for the open transaction.
+    /// Returns `false` if there's none, and the write has to go to the device.
+    pub(crate) fn hold_write(&mut self, offset: u64, data: &[u8]) -> Result<bool> {
+        let block_size = self.block_size();
+
+        let Some(journal) = self.journal.as_mut().filter(|j| j.in_transaction) else {
+            return Ok(false);
+        };
+
+        // Contents may land in a block freed earlier in the transaction.
+        let exact = journal.patch(offset, data);
+
+        if journal.writing_contents && !journal.journal_contents {
+            return Ok(false);
+        }
+
+        if !exact {
+            let held_size = journal.held_size + RECORD_HEADER_SIZE + data.len();
+
+            // Otherwise it would have to be written in place, and couldn't be rolled back.
+            if journal.journal_contents
+                && HEADER_SIZE + held_size > journal.chain.len() * block_size
+            {
+                return Err(NoctFSError::TooLarge);
+            }
+
+            journal.held_size = held_size;
+            journal.overlay.push((offset, data.to_vec()));
+        }
+
+        Ok(true)
+    }
+
+    /// Lays writes that aren't on the device yet over `data`, just read from `offset`.
+    pub(crate) fn apply_held_writes(&self, offset: u64, data: &mut [u8]) {
+        if let Some(journal) = &self.journal {
+            journal.apply(offset, data);
+        }
+    }
+
+    /// Writes the open transaction to the journal, then in place.
+    /// On failure, the caller has to roll it back.
+    fn commit(&mut self) -> Result<()> {
+        // Cached metadata becomes part of the transaction.
+        self.write_back_metadata()?;
+
+        let Some(journal) = &mut self.journal else {
+            return Ok(());
+        };
+
+        journal.in_transaction = false;
+        journal.journal_contents = false;
+        journal.held_size = 0;
+
+        let writes = core::mem::take(&mut journal.overlay);
+        let capacity = journal.chain.len() * self.block_size();
+
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let records = encode_records(&writes);
+
+        if HEADER_SIZE + records.len() <= capacity {
+            self.write_journal_area(HEADER_SIZE, &records)?;
+            self.device.flush()?;
+            self.write_journal_header(STATE_COMMITTED, writes.len(), &records)?;
+        } else {
+            // A crash from here on leaves the volume to the checker.
+            self.write_journal_header(STATE_IN_PLACE, 0, &[])?;
+        }
+
+        self.device.flush()?;
+
+        for (offset, data) in &writes {
+            self.device.write_all_at(*offset, data)?;
+        }
+
+        self.device.flush()?;
+
+        // A stale transaction replayed later would undo whatever comes after it.
+        self.clear_journal()
+    }
+
+    fn write_journal_header(&mut self, state: u32, count: usize, records: &[u8]) -> Result<()> {
+        let mut header = [0u8; HEADER_SIZE];
+
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&state.to_le_bytes());
+        header[12..16].copy_from_slice(&(count as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&(records.len() as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&checksum(records).to_le_bytes());
+
+        self.write_journal_area(0, &header)
+    }
+
+    fn clear_journal(&mut self) -> Result<()> {
+        self.write_journal_area(0, &[0; HEADER_SIZE])?;
+        self.device.flush()
+    }
+
+    /// Device offset of byte `position` of the journal chain, and how many bytes
+    /// from there on are in the same block.
+    fn journal_area_offset(&self, position: usize) -> (u64, usize) {
+        let block_size = self.block_size();
+        let chain = self.journal.as_ref().map_or(&[][..], |j| &j.chain);
+        let in_block = position % block_size;
+
+        (
+            self.datazone_offset_with_block(chain[position / block_size]) + in_block as u64,
+            block_size - in_block,
+        )
+    }
+
+    fn write_journal_area(&mut self, mut position: usize, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let (offset, left) = self.journal_area_offset(position);
+            let chunk = core::cmp::min(left, data.len());
+
+            self.device.write_all_at(offset, &data[..chunk])?;
+
+            position += chunk;
+            data = &data[chunk..];
+        }
+
+        Ok(())
+    }
+
+    fn read_journal_area(&mut self, mut position: usize, mut data: &mut [u8]) -> Result<()> {
+        while !data.is_empty() {
+            let (offset, left) = self.journal_area_offset(position);
+            let chunk = core::cmp::min(left, data.len());
+
+            self.device.read_exact_at(offset, &mut data[..chunk])?;
+
+            position += chunk;
+            data = &mut data[chunk..];
+        }
+
+        Ok(())
+    }
+}
@@ -2,97 +2,505 @@
 
 extern crate alloc;
 
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 use alloc::vec;
-use alloc::{boxed::Box, vec::Vec};
-
-use bootsector::BootSector;
-use device::Device;
-use entity::{Entity, EntityFlags};
-use no_std_io::io::{
-    self, Error,
-    SeekFrom::{Current, End, Start},
-};
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
 
+use access::{Access, Credentials};
+use bootsector::{
+    BootSector, IncompatFeatures, RoCompatFeatures, Uuid, VolumeInfo, VolumeState, BOOTSECTOR_SIZE,
+};
+use cache::{BlockMapCache, Page, ENTRIES_PER_PAGE, PAGE_SIZE};
+use clock::Clock;
+use device::{Device, DeviceHandle, ReadDevice};
+use entity::{Entity, Timestamps};
+use freemap::FreeMap;
+use journal::Journal;
+use link::LinkTable;
+use no_std_io::io::SeekFrom::{End, Start};
+use stats::EntityCounts;
+
+pub mod access;
 pub mod bootsector;
+mod cache;
+pub mod check;
+pub mod clock;
 pub mod device;
 pub mod entity;
+pub mod error;
+pub mod file;
+mod freemap;
+mod journal;
+mod link;
+mod path;
+pub mod stats;
+pub mod symlink;
+pub mod xattr;
+
+pub use error::{NoctFSError, Result};
 
 pub type BlockAddress = u64;
 
+/// Block map value of the last block in a chain.
+pub const END_OF_CHAIN: BlockAddress = 0xFFFF_FFFF_FFFF_FFFF;
+
 const ALLOWED_BLOCK_SIZES: &[u32] = &[512, 1024, 2048, 4096, 8192, 16384];
 const DEFAULT_BLOCK_SIZE: &u32 = &ALLOWED_BLOCK_SIZES[4]; // 8192
 const DEFAULT_SECTOR_SIZE: usize = 512;
 const FILESYSTEM_CODENAME: &[u8] = b"NoctFS__";
+/// Longest allowed entity name, in bytes.
+pub const MAX_NAME_LENGTH: usize = 255;
+/// Default memory budget for the block map cache.
+pub const DEFAULT_CACHE_SIZE: usize = 256 * 1024;
 
 const BLOCK_ADDRESS_SIZE: usize = core::mem::size_of::<BlockAddress>();
 
-#[derive(Debug)]
-pub enum NoctFSError {
-    SignatureNotValid,
-    OS(Error),
+/// Parses directory records up to the first empty header.
+///
+/// Returns each entity with its byte offset and, if parsing stopped at a malformed record, that record's offset.
+fn parse_records(data: &[u8]) -> (Vec<(usize, Entity)>, Option<usize>) {
+    let mut records = vec![];
+    let mut index = 0usize;
+
+    while index + 4 <= data.len() {
+        let header_size = u32::from_le_bytes(data[index..index + 4].try_into().unwrap());
+
+        if header_size == 0 {
+            break;
+        }
+
+        let entity = data
+            .get(index..index + header_size as usize + 4)
+            .and_then(Entity::from_raw);
+
+        match entity {
+            Some(entity) => records.push((index, entity)),
+            None => return (records, Some(index)),
+        }
+
+        index += header_size as usize + 4;
+    }
+
+    (records, None)
+}
+
+/// Parameters of a new volume, see [`NoctFS::format_with`].
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    /// Defaults to 512 bytes.
+    pub sector_size: Option<usize>,
+    /// Defaults to 8192 bytes.
+    pub block_size: Option<usize>,
+    /// Empty for no label.
+    pub label: String,
+    /// Defaults to a random UUID, or to [`Uuid::NIL`] without the `std` feature.
+    pub uuid: Option<Uuid>,
+    /// Size of the metadata journal in bytes, 0 for none.
+    /// Defaults to 1/32 of the volume, between 128 KiB and 16 MiB.
+    pub journal_size: Option<usize>,
+}
+
+/// How a volume is mounted, see [`NoctFS::mount`].
+#[derive(Debug, Clone, Copy)]
+pub struct MountOptions {
+    /// Reject every change with `ReadOnly`. Nothing is ever written to the device.
+    pub read_only: bool,
+    /// Write every change through to the device right away, instead of on `sync`.
+    pub sync: bool,
+    /// Don't record access times when files are read.
+    pub no_atime: bool,
+    /// Memory budget of the block map cache, in bytes.
+    pub cache_size: usize,
+    /// Mount read-write even if the volume wasn't unmounted cleanly and its journal
+    /// couldn't bring it back to a consistent state.
+    /// It stays marked dirty until [`NoctFS::repair`] runs.
+    pub allow_dirty: bool,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            sync: false,
+            no_atime: false,
+            cache_size: DEFAULT_CACHE_SIZE,
+            allow_dirty: false,
+        }
+    }
 }
 
 pub struct NoctFS<'dev> {
     bootsector: BootSector,
-    device: &'dev mut dyn Device,
+    device: DeviceHandle<'dev>,
+    options: MountOptions,
+    free_map: FreeMap,
+    /// Blocks holding the on-disk copy of `free_map`, empty if the volume has none.
+    free_map_chain: Vec<BlockAddress>,
+    /// Indices into `free_map_chain` of bitmap blocks changed since the last sync.
+    free_map_dirty: BTreeSet<usize>,
+    cache: BlockMapCache,
+    clock: Box<dyn Clock>,
+    /// Caller identity for permission checks, `None` if they're turned off.
+    credentials: Option<Credentials>,
+    /// Chains with more than one record, see `link.rs`.
+    links: LinkTable,
+    links_dirty: bool,
+    /// Record counts for `stats`, `None` until the tree is first counted.
+    entity_counts: Option<EntityCounts>,
+    /// Metadata journal, `None` if the volume has none.
+    journal: Option<Journal>,
+    /// Whether the bootsector on the device is marked dirty.
+    marked_dirty: bool,
+    /// Volume was mounted dirty and hasn't been repaired since, so it mustn't be marked clean.
+    needs_check: bool,
+    /// Whether mounting got through, so dropping the filesystem may sync and mark it clean.
+    mounted: bool,
+}
+
+/// Rejects names that can't be stored in a directory record or would break path resolution.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LENGTH
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\0'])
+    {
+        return Err(NoctFSError::InvalidName);
+    }
+
+    Ok(())
 }
 
 impl<'dev> NoctFS<'dev> {
-    pub fn new(device: &'dev mut dyn Device) -> Result<Self, NoctFSError> {
-        let mut bs_data = [0u8; 512];
+    /// Mounts the volume read-write.
+    ///
+    /// A volume that wasn't unmounted cleanly is brought back to a consistent state by
+    /// replaying its journal. Without a journal, that fails with `DirtyVolume`: run the checker
+    /// on a read-only mount first, or mount with [`MountOptions::allow_dirty`] to repair it.
+    pub fn new(device: &'dev mut dyn Device) -> Result<Self> {
+        Self::mount(device, MountOptions::default())
+    }
 
-        device.seek(Start(0)).map_err(NoctFSError::OS)?;
-        device.read(&mut bs_data).map_err(NoctFSError::OS)?;
+    pub fn mount(device: &'dev mut dyn Device, options: MountOptions) -> Result<Self> {
+        Self::mount_handle(DeviceHandle::ReadWrite(device), options)
+    }
 
-        let bootsector = BootSector::from_raw(&bs_data);
+    /// Mounts a device that can't be written to. The mount is read-only regardless of `options`.
+    pub fn mount_read_only(
+        device: &'dev mut dyn ReadDevice,
+        options: MountOptions,
+    ) -> Result<Self> {
+        Self::mount_handle(
+            DeviceHandle::ReadOnly(device),
+            MountOptions {
+                read_only: true,
+                ..options
+            },
+        )
+    }
 
-        if bootsector.filesystem_codename != FILESYSTEM_CODENAME {
-            return Err(NoctFSError::SignatureNotValid);
+    fn mount_handle(mut device: DeviceHandle<'dev>, options: MountOptions) -> Result<Self> {
+        let bootsector = BootSector::read(&mut device)?;
+
+        bootsector.check_features(options.read_only)?;
+
+        let dirty = bootsector.state == VolumeState::Dirty;
+
+        let block_count = bootsector.block_map_count as u64;
+
+        let mut fs = Self {
+            bootsector,
+            device,
+            options,
+            free_map: FreeMap::new(block_count),
+            free_map_chain: vec![],
+            free_map_dirty: BTreeSet::new(),
+            cache: BlockMapCache::new(options.cache_size),
+            clock: clock::default_clock(),
+            credentials: None,
+            links: LinkTable::new(),
+            links_dirty: false,
+            entity_counts: None,
+            journal: None,
+            marked_dirty: dirty,
+            needs_check: dirty,
+            mounted: false,
+        };
+
+        // Goes first, since the transaction it replays may touch everything else.
+        let consistent = fs.load_journal()?;
+
+        fs.needs_check = dirty && !consistent;
+
+        if fs.needs_check && !options.read_only && !options.allow_dirty {
+            return Err(NoctFSError::DirtyVolume);
         }
 
-        Ok(Self { bootsector, device })
+        fs.load_free_map()?;
+        fs.load_link_table()?;
+
+        fs.mounted = true;
+
+        Ok(fs)
     }
 
     pub fn format(
         device: &'dev mut dyn Device,
         sector_size: Option<usize>,
         block_size: Option<usize>,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
+        Self::format_with(
+            device,
+            &FormatOptions {
+                sector_size,
+                block_size,
+                ..FormatOptions::default()
+            },
+        )
+    }
+
+    pub fn format_with(device: &'dev mut dyn Device, options: &FormatOptions) -> Result<()> {
         let size = device.seek(End(0))?;
         device.seek(Start(0))?;
 
+        // Sizes are handled as `usize` from here on, which is only 32 bits wide on some targets.
+        let device_size = usize::try_from(size).map_err(|_| NoctFSError::TooLarge)?;
+
         let mut bootsector = BootSector::with_data(
-            size.try_into().unwrap(),
-            sector_size.unwrap_or(DEFAULT_SECTOR_SIZE) as _,
-            block_size.unwrap_or(*DEFAULT_BLOCK_SIZE as usize) as _,
+            device_size,
+            options.sector_size.unwrap_or(DEFAULT_SECTOR_SIZE) as _,
+            options.block_size.unwrap_or(*DEFAULT_BLOCK_SIZE as usize) as _,
         );
 
-        bootsector.first_root_entity_block = 1;
+        bootsector.label = bootsector::encode_label(&options.label)?;
+
+        #[cfg(feature = "std")]
+        let default_uuid = Uuid::random();
+        #[cfg(not(feature = "std"))]
+        let default_uuid = Uuid::NIL;
+
+        bootsector.uuid = options.uuid.unwrap_or(default_uuid);
+
+        bootsector.validate(size)?;
 
         // Write bootsector
 
         let sect = bootsector.as_raw();
-        device.write(&sect)?;
+        device.write_all(&sect)?;
 
         // Clear chainmap
-        let mut fs = Self::new(device).unwrap();
-
-        // Overwrite first 1MB
         let empty_block = vec![0u8; 1 << 20];
-        fs.device.seek(Start(fs.datazone_offset_with_block(1)))?;
-        fs.device.write(&empty_block)?;
+        let mut map_left = bootsector.block_map_count as usize * BLOCK_ADDRESS_SIZE;
+
+        device.seek(Start(bootsector.sector_size as u64))?;
+
+        while map_left > 0 {
+            let chunk = core::cmp::min(map_left, empty_block.len());
+
+            device.write_all(&empty_block[..chunk])?;
 
-        for i in 0..bootsector.block_map_count {
-            fs.write_block(i as BlockAddress, 0);
+            map_left -= chunk;
         }
 
+        let mut fs = Self::new(device)?;
+
+        // Overwrite first 1MB, without running past the end of a smaller volume
+        let clear_len = core::cmp::min(
+            empty_block.len(),
+            (bootsector.block_map_count as usize - 1) * fs.block_size(),
+        );
+
+        fs.write_at(fs.datazone_offset_with_block(1), &empty_block[..clear_len])?;
+
         // First block is always set as reserved
-        fs.write_block(0, 0xFFFF_FFFF_FFFF_FFFF);
+        fs.write_block(0, END_OF_CHAIN)?;
 
         // And finally, create a root directory.
         fs.create_root_directory()?;
 
+        fs.create_free_map()?;
+
+        let journal_size = options
+            .journal_size
+            .unwrap_or_else(|| journal::default_size(device_size));
+
+        if journal_size > 0 {
+            fs.create_journal(journal_size)?;
+        }
+
+        fs.unmount()
+    }
+
+    /// Writes every cached change back to the device.
+    pub fn sync(&mut self) -> Result<()> {
+        // Nothing can have changed.
+        if self.options.read_only {
+            return Ok(());
+        }
+
+        self.atomic(Self::write_back_metadata)?;
+
+        self.device.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the cached block map pages, free block bitmap and link table.
+    fn write_back_metadata(&mut self) -> Result<()> {
+        // Goes first, since resizing the table's chain changes the block map.
+        self.write_link_table()?;
+
+        self.write_back_pages()?;
+
+        while let Some(&index) = self.free_map_dirty.first() {
+            self.write_back_free_map(index)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_back_pages(&mut self) -> Result<()> {
+        for page_nr in self.cache.dirty_pages() {
+            self.write_back_page(page_nr)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether `write_back_metadata` has anything to write.
+    fn has_dirty_metadata(&self) -> bool {
+        self.links_dirty || !self.free_map_dirty.is_empty() || !self.cache.dirty_pages().is_empty()
+    }
+
+    fn write_back_page(&mut self, page_nr: u64) -> Result<()> {
+        let raw: Vec<u8> = match self.cache.get_mut(page_nr) {
+            Some(page) => page.entries.iter().flat_map(|e| e.to_le_bytes()).collect(),
+            None => return Ok(()),
+        };
+
+        self.write_at(self.map_page_offset(page_nr), &raw)?;
+
+        if let Some(page) = self.cache.get_mut(page_nr) {
+            page.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Writes block `index` of the free block bitmap chain.
+    fn write_back_free_map(&mut self, index: usize) -> Result<()> {
+        let block_size = self.block_size();
+        let start = index * block_size;
+        let end = core::cmp::min(
+            start + block_size,
+            FreeMap::byte_len(self.bootsector.block_map_count as u64),
+        );
+        let data: Vec<u8> = (start..end).map(|i| self.free_map.byte(i)).collect();
+
+        self.write_at(
+            self.datazone_offset_with_block(self.free_map_chain[index]),
+            &data,
+        )?;
+
+        self.free_map_dirty.remove(&index);
+
+        Ok(())
+    }
+
+    /// Syncs and releases the filesystem, reporting errors that dropping it would swallow.
+    pub fn unmount(mut self) -> Result<()> {
+        self.release()
+    }
+
+    /// Syncs and marks the volume clean, unless it was mounted dirty and hasn't been repaired.
+    fn release(&mut self) -> Result<()> {
+        self.sync()?;
+
+        if self.options.read_only || !self.marked_dirty || self.needs_check {
+            return Ok(());
+        }
+
+        // The journal is empty after every transaction.
+        self.bootsector.state = VolumeState::Clean;
+        self.bootsector
+            .incompat_features
+            .remove(IncompatFeatures::RECOVER);
+        self.write_volume_state()?;
+        self.marked_dirty = false;
+
+        Ok(())
+    }
+
+    /// Fails with `ReadOnly` on a read-only mount, and marks the volume dirty before its
+    /// first change otherwise.
+    fn begin_write(&mut self) -> Result<()> {
+        self.check_writable()?;
+
+        if self.marked_dirty {
+            return Ok(());
+        }
+
+        self.marked_dirty = true;
+        self.bootsector.state = VolumeState::Dirty;
+
+        if self.journal.is_some() {
+            self.bootsector
+                .incompat_features
+                .insert(IncompatFeatures::RECOVER);
+        }
+
+        self.write_volume_state()
+    }
+
+    /// Copies the volume state and the `RECOVER` flag to the bootsector on the device.
+    ///
+    /// Goes straight to the device, even if the first change is part of a transaction, and
+    /// leaves the rest of the on-disk copy alone: other bootsector changes only get there
+    /// once their transaction is committed.
+    fn write_volume_state(&mut self) -> Result<()> {
+        let mut raw = [0u8; BOOTSECTOR_SIZE];
+        self.device.read_exact_at(0, &mut raw)?;
+
+        let mut on_disk = BootSector::from_raw(&raw)?;
+        let recover = self
+            .bootsector
+            .incompat_features
+            .contains(IncompatFeatures::RECOVER);
+
+        on_disk.state = self.bootsector.state;
+        on_disk
+            .incompat_features
+            .set(IncompatFeatures::RECOVER, recover);
+
+        self.device.write_all_at(0, &on_disk.as_raw())?;
+        self.device.flush()
+    }
+
+    /// Lets a repaired volume be marked clean on unmount.
+    pub(crate) fn mark_checked(&mut self) {
+        self.needs_check = false;
+    }
+
+    /// Replaces the time source used for entity timestamps.
+    ///
+    /// With the `std` feature, the system clock is used by default.
+    /// Otherwise timestamps stay unknown until a clock is set.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Sets the memory budget of the block map cache, in bytes.
+    ///
+    /// At least one page of the block map is always kept in memory.
+    pub fn set_cache_limit(&mut self, limit: usize) -> Result<()> {
+        self.cache.set_limit(limit);
+
+        while let Some((page_nr, page)) = self.cache.evict() {
+            if page.dirty {
+                self.write_map_page(page_nr, &page)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -100,155 +508,413 @@ impl<'dev> NoctFS<'dev> {
         self.bootsector.block_size as usize
     }
 
-    pub fn find_block(&mut self) -> Option<BlockAddress> {
-        for i in 0..self.bootsector.block_map_count {
-            let blk = self.get_block(i as _);
+    pub fn mount_options(&self) -> &MountOptions {
+        &self.options
+    }
 
-            if let Some(0) = blk {
-                return Some(i as u64);
-            }
+    /// Fails with `ReadOnly` on a read-only mount.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        match self.options.read_only {
+            true => Err(NoctFSError::ReadOnly),
+            false => Ok(()),
         }
+    }
 
-        None
+    /// Geometry and identity of the mounted volume.
+    pub fn info(&self) -> VolumeInfo {
+        self.bootsector.info()
     }
 
-    pub fn get_block(&mut self, nr: BlockAddress) -> Option<BlockAddress> {
-        if nr >= self.bootsector.block_map_count as u64 {
-            return None;
+    /// Changes the volume label. With credentials set, only root may do that.
+    pub fn set_label(&mut self, label: &str) -> Result<()> {
+        self.check_writable()?;
+
+        if self
+            .credentials
+            .as_ref()
+            .is_some_and(|credentials| !credentials.is_root())
+        {
+            return Err(NoctFSError::PermissionDenied);
         }
 
-        let offset =
-            self.bootsector.sector_size as BlockAddress + (nr * BLOCK_ADDRESS_SIZE as BlockAddress);
-        let mut block_raw: [u8; BLOCK_ADDRESS_SIZE] = [0; BLOCK_ADDRESS_SIZE];
+        self.bootsector.label = bootsector::encode_label(label)?;
+
+        self.write_bootsector()
+    }
 
-        self.device.seek(Start(offset as _)).unwrap();
-        self.device.read(&mut block_raw).unwrap();
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> Result<()> {
+        self.device.read_exact_at(offset, data)?;
+        self.apply_held_writes(offset, data);
 
-        Some(u64::from_le_bytes(block_raw))
+        Ok(())
     }
 
-    pub fn write_block(&mut self, nr: BlockAddress, value: BlockAddress) {
-        if nr >= self.bootsector.block_map_count as BlockAddress {
-            return;
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.begin_write()?;
+
+        if self.hold_write(offset, data)? {
+            return Ok(());
         }
 
-        let offset =
-            self.bootsector.sector_size as BlockAddress + (nr * BLOCK_ADDRESS_SIZE as BlockAddress);
-        let block_raw: [u8; BLOCK_ADDRESS_SIZE] = value.to_le_bytes();
+        self.device.write_all_at(offset, data)?;
+
+        if self.options.sync {
+            self.device.flush()?;
+        }
 
-        self.device.seek(Start(offset as _)).unwrap();
-        self.device.write(&block_raw).unwrap();
+        Ok(())
     }
 
-    pub fn allocate_blocks(&mut self, count: u32) -> Option<BlockAddress> {
-        if count == 0 {
-            return None;
+    fn write_bootsector(&mut self) -> Result<()> {
+        let sect = self.bootsector.as_raw();
+
+        self.write_at(0, &sect)
+    }
+
+    /// Loads the free block bitmap, or builds it from the block map on volumes that have none.
+    fn load_free_map(&mut self) -> Result<()> {
+        let block_count = self.bootsector.block_map_count as u64;
+        let free_map_block = self.bootsector.free_map_block;
+
+        if free_map_block != 0 {
+            let chain = self.get_chain(free_map_block)?;
+            let mut data = vec![0u8; chain.len() * self.block_size()];
+
+            if data.len() < FreeMap::byte_len(block_count) {
+                return Err(NoctFSError::Corrupted {
+                    block: free_map_block,
+                });
+            }
+
+            self.read_blocks_data(free_map_block, &mut data, 0)?;
+
+            self.free_map = FreeMap::from_bytes(block_count, &data);
+            self.free_map_chain = chain.into_vec();
+
+            return Ok(());
         }
 
-        let first_block = self.find_block();
-        let mut previous_block = first_block;
+        const CHUNK_ENTRIES: u64 = 4096;
 
-        for _ in 0..count {
-            let new_block = self.find_block().unwrap();
+        let mut free_map = FreeMap::new(block_count);
+        let mut raw = vec![0u8; CHUNK_ENTRIES as usize * BLOCK_ADDRESS_SIZE];
 
-            #[cfg(feature = "std")]
-            println!("Found new block: {}", new_block);
+        for first in (0..block_count).step_by(CHUNK_ENTRIES as usize) {
+            let entries = core::cmp::min(CHUNK_ENTRIES, block_count - first) as usize;
+            let raw = &mut raw[..entries * BLOCK_ADDRESS_SIZE];
 
-            self.write_block(previous_block.unwrap(), new_block);
-            self.write_block(new_block, 0xFFFF_FFFF_FFFF_FFFF);
+            self.read_at(
+                self.bootsector.sector_size as u64 + first * BLOCK_ADDRESS_SIZE as u64,
+                raw,
+            )?;
 
-            previous_block = Some(new_block);
+            for (nr, entry) in raw.chunks_exact(BLOCK_ADDRESS_SIZE).enumerate() {
+                if entry.iter().any(|&b| b != 0) {
+                    free_map.set(first + nr as u64, true);
+                }
+            }
         }
 
-        // Last block in chain
-        self.write_block(previous_block.unwrap(), 0xFFFF_FFFF_FFFF_FFFF);
+        self.free_map = free_map;
 
-        first_block
+        Ok(())
     }
 
-    pub fn get_chain(&mut self, start_block: BlockAddress) -> Box<[u64]> {
-        let mut blocks: Vec<BlockAddress> = vec![];
-        let mut current_block = start_block;
+    /// Allocates the on-disk bitmap and records it in the bootsector.
+    fn create_free_map(&mut self) -> Result<()> {
+        let byte_len = FreeMap::byte_len(self.bootsector.block_map_count as u64);
+        let start_block = self.allocate_bytes(byte_len)?;
 
-        while let Some(block) = self.get_block(current_block) {
-            blocks.push(current_block);
+        self.free_map_chain = self.get_chain(start_block)?.into_vec();
+
+        let data: Vec<u8> = (0..byte_len).map(|i| self.free_map.byte(i)).collect();
+        self.write_blocks_data(start_block, &data, 0)?;
+
+        self.bootsector.free_map_block = start_block;
+        self.bootsector
+            .ro_compat_features
+            .insert(RoCompatFeatures::FREE_MAP);
+        self.write_bootsector()
+    }
+
+    /// Reflects a block map change in the free block bitmap.
+    fn mark_block(&mut self, nr: BlockAddress, used: bool) -> Result<()> {
+        self.begin_write()?;
+
+        if !self.free_map.set(nr, used) || self.free_map_chain.is_empty() {
+            return Ok(());
+        }
+
+        let chain_index = (nr / 8) as usize / self.block_size();
+
+        if chain_index >= self.free_map_chain.len() {
+            return Err(NoctFSError::Corrupted {
+                block: self.bootsector.free_map_block,
+            });
+        }
+
+        self.free_map_dirty.insert(chain_index);
+
+        if self.options.sync {
+            self.write_back_free_map(chain_index)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn find_block(&mut self) -> Result<BlockAddress> {
+        while let Some(block) = self.free_map.find_free() {
+            // The block map has the final word: skip blocks the bitmap lost track of.
+            if let Some(0) = self.get_block(block)? {
+                return Ok(block);
+            }
+
+            self.mark_block(block, true)?;
+        }
+
+        Err(NoctFSError::NoSpace)
+    }
+
+    #[inline]
+    fn map_page_offset(&self, page_nr: u64) -> u64 {
+        self.bootsector.sector_size as u64 + page_nr * PAGE_SIZE as u64
+    }
+
+    fn write_map_page(&mut self, page_nr: u64, page: &Page) -> Result<()> {
+        let raw: Vec<u8> = page.entries.iter().flat_map(|e| e.to_le_bytes()).collect();
+
+        self.write_at(self.map_page_offset(page_nr), &raw)
+    }
+
+    /// Returns the cached page holding entry `nr`, reading it from the device if needed.
+    fn map_page(&mut self, nr: BlockAddress) -> Result<&mut Page> {
+        let page_nr = nr / ENTRIES_PER_PAGE;
+
+        if !self.cache.contains(page_nr) {
+            if let Some((victim_nr, victim)) = self.cache.evict() {
+                if victim.dirty {
+                    self.write_map_page(victim_nr, &victim)?;
+                }
+            }
+
+            let first = page_nr * ENTRIES_PER_PAGE;
+            let entries = core::cmp::min(
+                ENTRIES_PER_PAGE,
+                self.bootsector.block_map_count as u64 - first,
+            ) as usize;
+            let mut raw = vec![0u8; entries * BLOCK_ADDRESS_SIZE];
+
+            self.read_at(self.map_page_offset(page_nr), &mut raw)?;
+
+            let entries = raw
+                .chunks_exact(BLOCK_ADDRESS_SIZE)
+                .map(|e| u64::from_le_bytes(e.try_into().unwrap()))
+                .collect();
+
+            self.cache.insert(page_nr, entries);
+        }
+
+        self.cache
+            .get_mut(page_nr)
+            .ok_or(NoctFSError::Corrupted { block: nr })
+    }
+
+    pub fn get_block(&mut self, nr: BlockAddress) -> Result<Option<BlockAddress>> {
+        if nr >= self.bootsector.block_map_count as u64 {
+            return Ok(None);
+        }
+
+        let page = self.map_page(nr)?;
+
+        Ok(Some(page.entries[(nr % ENTRIES_PER_PAGE) as usize]))
+    }
+
+    pub fn write_block(&mut self, nr: BlockAddress, value: BlockAddress) -> Result<()> {
+        if nr >= self.bootsector.block_map_count as BlockAddress {
+            return Err(NoctFSError::Corrupted { block: nr });
+        }
+
+        self.begin_write()?;
+
+        let page = self.map_page(nr)?;
+
+        page.entries[(nr % ENTRIES_PER_PAGE) as usize] = value;
+        page.dirty = true;
+
+        if self.options.sync {
+            self.write_back_page(nr / ENTRIES_PER_PAGE)?;
+        }
+
+        self.mark_block(nr, value != 0)
+    }
+
+    /// Allocates a chain of `count` blocks and returns its first block.
+    ///
+    /// Allocating zero blocks yields block 0, which is reserved and never starts a chain.
+    /// If the volume runs out of space midway, already allocated blocks are released.
+    pub fn allocate_blocks(&mut self, count: u32) -> Result<BlockAddress> {
+        if count == 0 {
+            return Ok(0);
+        }
 
-            current_block = block;
+        if count as u64 > self.free_map.free_count() {
+            return Err(NoctFSError::NoSpace);
         }
 
-        // blocks.push(current_block);
+        let first_block = self.find_block()?;
+        self.write_block(first_block, END_OF_CHAIN)?;
+
+        let mut previous_block = first_block;
+
+        for _ in 1..count {
+            let new_block = match self.find_block() {
+                Ok(block) => block,
+                Err(e) => {
+                    self.free_blocks(first_block)?;
+                    return Err(e);
+                }
+            };
+
+            self.write_block(previous_block, new_block)?;
+            self.write_block(new_block, END_OF_CHAIN)?;
 
-        blocks.into_boxed_slice()
+            previous_block = new_block;
+        }
+
+        Ok(first_block)
     }
 
-    pub fn free_blocks(&mut self, start_block: BlockAddress) {
+    /// Collects the blocks of the chain starting at `start_block`.
+    ///
+    /// Fails with `Corrupted` if the chain links to a free or nonexistent block, or loops back onto itself.
+    /// Block 0 is reserved, so a chain starting there is empty.
+    pub fn get_chain(&mut self, start_block: BlockAddress) -> Result<Box<[u64]>> {
+        let mut blocks: Vec<BlockAddress> = vec![];
+
         if start_block == 0 {
-            return;
+            return Ok(blocks.into_boxed_slice());
         }
 
+        let mut visited = BTreeSet::from([start_block]);
         let mut current_block = start_block;
 
-        while let Some(block) = self.get_block(current_block) {
-            #[cfg(feature = "std")]
-            println!("Clear block: {}", current_block);
+        loop {
+            let next_block = match self.get_block(current_block)? {
+                // Block is free or doesn't exist, yet something links to it.
+                Some(0) | None => {
+                    return Err(NoctFSError::Corrupted {
+                        block: current_block,
+                    })
+                }
+                Some(block) => block,
+            };
+
+            blocks.push(current_block);
 
-            if block == 0xFFFF_FFFF_FFFF_FFFF {
-                self.write_block(current_block, 0);
+            if next_block == END_OF_CHAIN {
                 break;
             }
 
-            self.write_block(current_block, 0);
+            // Linking back to a block already walked would go round forever.
+            if !visited.insert(next_block) {
+                return Err(NoctFSError::Corrupted {
+                    block: current_block,
+                });
+            }
+
+            current_block = next_block;
+        }
+
+        Ok(blocks.into_boxed_slice())
+    }
 
-            current_block = block;
+    /// Releases every block of the chain. The chain is validated before anything is freed.
+    pub fn free_blocks(&mut self, start_block: BlockAddress) -> Result<()> {
+        for block in self.get_chain(start_block)? {
+            self.write_block(block, 0)?;
         }
+
+        Ok(())
     }
 
-    pub fn extend_chain_by(&mut self, start_block: BlockAddress, count: usize) {
-        let chain = self.get_chain(start_block);
+    pub fn extend_chain_by(&mut self, start_block: BlockAddress, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        let chain = self.get_chain(start_block)?;
 
-        let last = chain.last().unwrap();
+        let last = *chain
+            .last()
+            .ok_or(NoctFSError::Corrupted { block: start_block })?;
 
-        let allocated = self.allocate_blocks(count as u32).unwrap();
+        let allocated = self.allocate_blocks(count as u32)?;
 
-        self.write_block(*last, allocated);
+        self.write_block(last, allocated)
     }
 
-    pub fn shrink_chain_by(&mut self, start_block: BlockAddress, count: usize) {
-        let chain = self.get_chain(start_block);
+    /// Cuts `count` blocks off the end of the chain and frees them.
+    ///
+    /// Removing the whole chain frees every block, like `free_blocks` does.
+    pub fn shrink_chain_by(&mut self, start_block: BlockAddress, count: usize) -> Result<()> {
+        let chain = self.get_chain(start_block)?;
 
         if count == 0 {
-            return;
+            return Ok(());
         }
 
         if count > chain.len() {
-            return;
+            return Ok(());
         }
 
-        let work_area = &chain[chain.len() - count - 1..];
+        let kept = chain.len() - count;
 
-        self.write_block(work_area[0], 0xFFFF_FFFF_FFFF_FFFF);
+        // Terminate the chain first, so it stays valid if we stop halfway.
+        if let Some(&last) = kept.checked_sub(1).map(|index| &chain[index]) {
+            self.write_block(last, END_OF_CHAIN)?;
+        }
 
-        for i in &work_area[1..] {
-            self.write_block(*i, 0);
+        for &block in &chain[kept..] {
+            self.write_block(block, 0)?;
         }
+
+        Ok(())
     }
 
-    pub fn set_chain_size(&mut self, start_block: BlockAddress, count: usize) {
-        let chain_length = self.get_chain(start_block).len();
+    pub fn set_chain_size(&mut self, start_block: BlockAddress, count: usize) -> Result<()> {
+        let chain_length = self.get_chain(start_block)?.len();
 
         if chain_length > count {
-            self.shrink_chain_by(start_block, chain_length - count);
+            self.shrink_chain_by(start_block, chain_length - count)
         } else if chain_length < count {
-            self.extend_chain_by(start_block, count - chain_length);
+            self.extend_chain_by(start_block, count - chain_length)
+        } else {
+            Ok(())
         }
     }
 
-    pub fn allocate_bytes(&mut self, byte_count: usize) -> Option<u64> {
+    pub fn allocate_bytes(&mut self, byte_count: usize) -> Result<BlockAddress> {
         let blocks = byte_count.div_ceil(self.bootsector.block_size as usize);
 
         self.allocate_blocks(blocks as _)
     }
 
+    /// Overwrites bytes `from..to` of the file's contents with zeroes.
+    fn zero_range(&mut self, start_block: BlockAddress, from: u64, to: u64) -> Result<()> {
+        let zeroes = vec![0u8; core::cmp::min(to.saturating_sub(from), 1 << 20) as usize];
+        let mut offset = from;
+
+        while offset < to {
+            let chunk = core::cmp::min(to - offset, zeroes.len() as u64) as usize;
+
+            self.write_contents(start_block, &zeroes[..chunk], offset)?;
+
+            offset += chunk as u64;
+        }
+
+        Ok(())
+    }
+
     #[inline]
     pub fn datazone_offset(&self) -> usize {
         self.bootsector.sector_size as usize
@@ -266,18 +932,12 @@ impl<'dev> NoctFS<'dev> {
         start_block: BlockAddress,
         data: &mut [u8],
         offset: u64,
-    ) -> io::Result<usize> {
-        let chain = self.get_chain(start_block);
+    ) -> Result<usize> {
+        let chain = self.get_chain(start_block)?;
         let chain_off = (offset / self.bootsector.block_size as u64) as usize;
         let first_occurency_offset = offset % self.bootsector.block_size as u64;
 
         if chain_off > chain.len() {
-            #[cfg(feature = "std")]
-            println!(
-                "Chain offset ({}) is large than chain length ({})",
-                chain_off,
-                chain.len()
-            );
             return Ok(0);
         }
 
@@ -285,37 +945,31 @@ impl<'dev> NoctFS<'dev> {
 
         let mut data_length = data.len();
 
-        #[cfg(feature = "std")]
-        println!("--- {offset} {} fco: {first_occurency_offset}", data.len());
-
         let mut readbytes = 0usize;
 
         for (nr, &i) in chain.iter().enumerate() {
-            let mut read_size = core::cmp::min(data_length, self.bootsector.block_size as usize);
-
-            if read_size == 0 {
+            if data_length == 0 {
                 break;
             }
 
-            let f_offset = self.datazone_offset_with_block(i);
-
-            self.device.seek(Start(f_offset))?;
+            let mut f_offset = self.datazone_offset_with_block(i);
 
-            if nr == 0 {
-                self.device.seek(Current(first_occurency_offset as _))?;
+            let read_size = if nr == 0 && first_occurency_offset != 0 {
+                f_offset += first_occurency_offset;
 
-                read_size -= first_occurency_offset as usize;
-            }
+                // Only the tail of the first block is available after the offset
+                let available_in_first_block =
+                    (self.bootsector.block_size as u64 - first_occurency_offset) as usize;
+                core::cmp::min(data_length, available_in_first_block)
+            } else {
+                core::cmp::min(data_length, self.bootsector.block_size as usize)
+            };
 
             // let data_offset = nr as u64 * self.bootsector.block_size as u64;
             let data_offset = readbytes;
             let end_offset = data_offset + read_size;
 
-            #[cfg(feature = "std")]
-            println!("{:?}", data_offset as usize..end_offset as usize);
-
-            self.device
-                .read(&mut data[data_offset as usize..end_offset as usize])?;
+            self.read_at(f_offset, &mut data[data_offset..end_offset])?;
 
             data_length -= read_size;
             readbytes += read_size;
@@ -329,9 +983,9 @@ impl<'dev> NoctFS<'dev> {
         start_block: BlockAddress,
         data: &[u8],
         offset: u64,
-    ) -> io::Result<usize> {
+    ) -> Result<usize> {
         // Get the chain of blocks.
-        let chain: Box<[BlockAddress]> = self.get_chain(start_block);
+        let chain: Box<[BlockAddress]> = self.get_chain(start_block)?;
 
         // Calculate offsets
         let chain_off = (offset / self.bootsector.block_size as u64) as usize;
@@ -345,16 +999,8 @@ impl<'dev> NoctFS<'dev> {
         // Crop the chain to the working area.
         let chain = &chain[chain_off..];
 
-        #[cfg(feature = "std")]
-        println!("{:?}", &chain);
-
         let mut data_length = data.len();
 
-        #[cfg(feature = "std")]
-        println!(
-            "----- Write: data length: {data_length}; offset: {offset}; {first_occurency_offset}"
-        );
-
         let mut written = 0usize;
 
         for (nr, &i) in chain.iter().enumerate() {
@@ -362,8 +1008,7 @@ impl<'dev> NoctFS<'dev> {
                 break;
             }
 
-            let f_offset: u64 = self.datazone_offset_with_block(i);
-            self.device.seek(Start(f_offset))?;
+            let mut f_offset: u64 = self.datazone_offset_with_block(i);
 
             let write_size = if nr == 0 && first_occurency_offset != 0 {
                 // Calculate available space after the offset in the first block
@@ -375,13 +1020,13 @@ impl<'dev> NoctFS<'dev> {
             };
 
             if nr == 0 && first_occurency_offset != 0 {
-                self.device.seek(Current(first_occurency_offset as i64))?;
+                f_offset += first_occurency_offset;
             }
 
             let data_offset = written;
             let end_offset = data_offset + write_size;
 
-            self.device.write_all(&data[data_offset..end_offset])?;
+            self.write_at(f_offset, &data[data_offset..end_offset])?;
 
             data_length -= write_size;
             written += write_size;
@@ -390,177 +1035,268 @@ impl<'dev> NoctFS<'dev> {
         Ok(written)
     }
 
-    fn create_root_directory(&mut self) -> io::Result<u64> {
-        let block = self.allocate_blocks(1).unwrap();
+    fn create_root_directory(&mut self) -> Result<BlockAddress> {
+        let block = self.allocate_blocks(1)?;
         //let block_container = self.allocate_blocks(1);
         //let entity = Entity::directory("(root)", 0, block_container.unwrap());
         //let data = entity.as_raw();
 
         //self.write_blocks_data(block.unwrap(), &data, 0)?;
 
-        let this_entity = Entity::directory(".", 0, block);
-        let parent_entity = Entity::directory("..", 0, block);
+        let times = Timestamps::at(self.clock.now());
+
+        let mut this_entity = Entity::directory(".", 0, block);
+        let mut parent_entity = Entity::directory("..", 0, block);
+        this_entity.times = times;
+        parent_entity.times = times;
 
-        self.write_entity(block, &this_entity);
-        self.write_entity(block, &parent_entity);
+        self.write_entity(block, &this_entity)?;
+        self.write_entity(block, &parent_entity)?;
 
         Ok(block)
     }
 
-    pub fn get_root_entity(&mut self) -> io::Result<Entity> {
-        // let chain_size = self.get_chain(1).len();
-        // let mut data = vec![0u8; chain_size * self.bootsector.block_size as usize];
+    pub fn get_root_entity(&mut self) -> Result<Entity> {
+        let block = self.bootsector.first_root_entity_block;
 
-        // self.read_blocks_data(1, data.as_mut_slice(), 0)?;
+        // The root has no record of its own, so its `.` record carries its metadata.
+        // A damaged root still has to be reachable, e.g. for the checker.
+        let mut root = match self.find_entity(block, ".") {
+            Ok(this) => this,
+            Err(NoctFSError::Io(e)) => return Err(NoctFSError::Io(e)),
+            Err(_) => Entity::directory("/", 0, block),
+        };
 
-        Ok(Entity {
-            name: "/".to_string(),
-            size: 0,
-            start_block: 1,
-            flags: EntityFlags::DIRECTORY,
-            vendor_data_size: 0,
-        })
+        root.name = "/".to_string();
+        root.start_block = block;
+
+        Ok(root)
     }
 
-    fn read_chain_data_vec(&mut self, start_block: BlockAddress) -> Vec<u8> {
-        let chain_size = self.get_chain(start_block).len();
+    fn read_chain_data_vec(&mut self, start_block: BlockAddress) -> Result<Vec<u8>> {
+        let chain_size = self.get_chain(start_block)?.len();
         let mut data = vec![0u8; chain_size * self.bootsector.block_size as usize];
 
-        self.read_blocks_data(start_block, data.as_mut_slice(), 0)
-            .unwrap();
+        self.read_blocks_data(start_block, data.as_mut_slice(), 0)?;
 
-        data
+        Ok(data)
+    }
+
+    /// Parses every record of the directory, returning each entity with its byte offset.
+    fn read_records(&mut self, directory_block: BlockAddress) -> Result<Vec<(usize, Entity)>> {
+        let data = self.read_chain_data_vec(directory_block)?;
+
+        match parse_records(&data) {
+            (records, None) => Ok(records),
+            (_, Some(_)) => Err(NoctFSError::Corrupted {
+                block: directory_block,
+            }),
+        }
     }
 
     pub fn allocate_for_entity(
         &mut self,
         directory_block: BlockAddress,
         entity: &Entity,
-    ) -> Option<usize> {
-        let mut data = self.read_chain_data_vec(directory_block);
+    ) -> Result<usize> {
+        let mut data = self.read_chain_data_vec(directory_block)?;
 
         let mut index = 0usize;
 
         // Find free space
-        while index < data.len() {
-            let header_size = u32::from_le_bytes(data[index..index+4].try_into().unwrap());
-
-            #[cfg(feature = "std")]
-            println!("[{index} / {}] Header size: {}", data.len(), header_size);
+        while index + 4 <= data.len() {
+            let header_size = u32::from_le_bytes(data[index..index + 4].try_into().unwrap());
 
             if header_size == 0 {
-                return Some(index);
+                return Ok(index);
             }
 
             index += header_size as usize + 4;
-            
-            if entity.fact_size() >= (data.len() - index) as _ {
-                self.extend_chain_by(directory_block, 1);
-
-                #[cfg(feature = "std")]
-                println!("=== Extending chain!");
 
-                data = self.read_chain_data_vec(directory_block);
+            if index > data.len() {
+                return Err(NoctFSError::Corrupted {
+                    block: directory_block,
+                });
             }
-            
 
-         }
+            let free_space = data.len() - index;
+            let needed = entity.fact_size() as usize;
 
-        None
-    }
+            if needed >= free_space {
+                // A record may be larger than a block, e.g. one carrying extended attributes.
+                let blocks = (needed + 1 - free_space).div_ceil(self.block_size());
 
-    pub fn write_entity(&mut self, directory_block: BlockAddress, entity: &Entity) {
-        let allocated = self.allocate_for_entity(directory_block, entity).unwrap();
-        let mut data = self.read_chain_data_vec(directory_block);
-        let raw_entity = entity.as_raw();
+                self.extend_chain_by(directory_block, blocks)?;
 
-        data[allocated..allocated + raw_entity.len()].copy_from_slice(&raw_entity);
+                // A reused block still holds whatever was written there before,
+                // which would be parsed as records.
+                let old_len = data.len();
+                data.resize(old_len + blocks * self.block_size(), 0);
 
-        self.write_blocks_data(directory_block, &data, 0).unwrap();
-    }
+                self.write_blocks_data(directory_block, &data[old_len..], old_len as _)?;
+            }
+        }
 
-    pub fn create_directory<T: ToString>(&mut self, directory_block: u64, name: T) -> Entity {
-        let block = self.allocate_blocks(1).unwrap();
-        let entity = Entity::directory(name, 0, block);
+        Err(NoctFSError::Corrupted {
+            block: directory_block,
+        })
+    }
 
-        self.write_entity(directory_block, &entity);
+    pub fn write_entity(&mut self, directory_block: BlockAddress, entity: &Entity) -> Result<()> {
+        let allocated = self.allocate_for_entity(directory_block, entity)?;
+        let mut data = self.read_chain_data_vec(directory_block)?;
+        let raw_entity = entity.as_raw();
 
-        let this_entity = Entity::directory(".", 0, block);
-        let parent_entity = Entity::directory("..", 0, directory_block);
+        data[allocated..allocated + raw_entity.len()].copy_from_slice(&raw_entity);
 
-        self.write_entity(block, &this_entity);
-        self.write_entity(block, &parent_entity);
+        self.write_blocks_data(directory_block, &data, 0)?;
 
-        entity
+        Ok(())
     }
 
-    pub fn create_file<T: ToString>(&mut self, directory_block: u64, name: T) -> Entity {
-        let block = self.allocate_blocks(1).unwrap();
-        let entity = Entity::file(name, 0, block);
+    fn check_new_name(&mut self, directory_block: BlockAddress, name: &str) -> Result<()> {
+        validate_name(name)?;
+
+        match self.find_entity(directory_block, name) {
+            Ok(_) => Err(NoctFSError::AlreadyExists),
+            Err(NoctFSError::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
 
-        self.write_entity(directory_block, &entity);
+    pub fn create_directory<T: ToString>(
+        &mut self,
+        directory_block: BlockAddress,
+        name: T,
+    ) -> Result<Entity> {
+        let name = name.to_string();
 
-        entity
+        self.atomic(|fs| fs.create_directory_inner(directory_block, name))
     }
 
-    pub fn get_entity_offset(
+    fn create_directory_inner(
         &mut self,
         directory_block: BlockAddress,
-        entity: &Entity,
-    ) -> Option<usize> {
-        let data = self.read_chain_data_vec(directory_block);
-        let raw_data = entity.as_raw();
+        name: String,
+    ) -> Result<Entity> {
+        self.check_directory_access(directory_block, Access::WRITE | Access::EXECUTE)?;
+        self.check_new_name(directory_block, &name)?;
 
-        let mut index = 0usize;
+        let block = self.allocate_blocks(1)?;
 
-        while index < data.len() {
-            let header_size = u32::from_le_bytes(data[index..index+4].try_into().unwrap());
+        // Same as in `allocate_for_entity`: records must start from a zeroed block.
+        let zeroes = vec![0u8; self.bootsector.block_size as usize];
 
-            #[cfg(feature = "std")]
-            println!("[{} / {}] Header size: {header_size}", index, data.len());
+        if let Err(e) = self.write_blocks_data(block, &zeroes, 0) {
+            self.free_blocks(block)?;
+            return Err(e);
+        }
 
-            if header_size == 0 {
-                #[cfg(feature = "std")]
-                println!("empty header");
-                break;
-            }
+        let times = Timestamps::at(self.clock.now());
 
-            if data[index..index + raw_data.len()] == *raw_data {
-                return Some(index);
-            }
+        let (uid, gid) = self.new_owner();
 
-            index += header_size as usize + 4;
+        let mut entity = Entity::directory(name, 0, block);
+        entity.times = times;
+        entity.uid = uid;
+        entity.gid = gid;
+
+        if let Err(e) = self.write_entity(directory_block, &entity) {
+            self.free_blocks(block)?;
+            return Err(e);
         }
 
-        None
+        let mut this_entity = entity.clone();
+        let mut parent_entity = Entity::directory("..", 0, directory_block);
+        this_entity.name = ".".to_string();
+        parent_entity.times = times;
+
+        self.write_entity(block, &this_entity)?;
+        self.write_entity(block, &parent_entity)?;
+
+        self.touch_directory(directory_block)?;
+        self.count_entity(&entity, true);
+
+        Ok(entity)
     }
 
-    pub fn get_entity_by_parent_and_block(
+    pub fn create_file<T: ToString>(
         &mut self,
         directory_block: BlockAddress,
-        entity_block: BlockAddress,
-    ) -> Option<Entity> {
-        let data = self.read_chain_data_vec(directory_block);
-        let mut index = 0usize;
+        name: T,
+    ) -> Result<Entity> {
+        let name = name.to_string();
 
-        while index < data.len() {
-            let header_size = u32::from_le_bytes(data[index..index+4].try_into().unwrap());
+        self.atomic(|fs| fs.create_file_inner(directory_block, name))
+    }
 
-            if header_size == 0 {
-                break;
-            }
+    fn create_file_inner(&mut self, directory_block: BlockAddress, name: String) -> Result<Entity> {
+        self.check_directory_access(directory_block, Access::WRITE | Access::EXECUTE)?;
+        self.check_new_name(directory_block, &name)?;
 
-            let cur_entity = Entity::from_raw(&data[index..index + header_size as usize + 4]);
-            // println!("{} {}", cur_entity.name, entity.name);
+        let block = self.allocate_blocks(1)?;
+        let (uid, gid) = self.new_owner();
 
-            if cur_entity.start_block == entity_block {
-                return Some(cur_entity);
-            }
+        let mut entity = Entity::file(name, 0, block);
+        entity.times = Timestamps::at(self.clock.now());
+        entity.uid = uid;
+        entity.gid = gid;
 
-            index += header_size as usize + 4;
+        if let Err(e) = self.write_entity(directory_block, &entity) {
+            self.free_blocks(block)?;
+            return Err(e);
         }
 
-        None
+        self.touch_directory(directory_block)?;
+        self.count_entity(&entity, true);
+
+        Ok(entity)
+    }
+
+    pub fn find_entity(&mut self, directory_block: BlockAddress, name: &str) -> Result<Entity> {
+        self.read_records(directory_block)?
+            .into_iter()
+            .map(|(_, entity)| entity)
+            .find(|entity| entity.name == name)
+            .ok_or(NoctFSError::NotFound)
+    }
+
+    /// Finds the entity's record in the directory, returning its offset and the on-disk copy.
+    ///
+    /// Records are matched by name and start block rather than byte-for-byte,
+    /// so a caller holding a copy with an outdated size still finds its record.
+    fn find_record(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<(usize, Entity)> {
+        self.read_records(directory_block)?
+            .into_iter()
+            .find(|(_, cur_entity)| {
+                cur_entity.name == entity.name && cur_entity.start_block == entity.start_block
+            })
+            .ok_or(NoctFSError::NotFound)
+    }
+
+    pub fn get_entity_offset(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<usize> {
+        self.find_record(directory_block, entity)
+            .map(|(index, _)| index)
+    }
+
+    pub fn get_entity_by_parent_and_block(
+        &mut self,
+        directory_block: BlockAddress,
+        entity_block: BlockAddress,
+    ) -> Result<Entity> {
+        self.read_records(directory_block)?
+            .into_iter()
+            .map(|(_, entity)| entity)
+            .find(|entity| entity.start_block == entity_block)
+            .ok_or(NoctFSError::NotFound)
     }
 
     pub fn write_contents_by_entity(
@@ -569,95 +1305,571 @@ impl<'dev> NoctFS<'dev> {
         entity: &Entity,
         data: &[u8],
         offset: u64,
-    ) -> io::Result<usize> {
-        let block = entity.start_block;
+    ) -> Result<usize> {
+        self.atomic(|fs| fs.write_contents_inner(directory_block, entity, data, offset))
+    }
+
+    fn write_contents_inner(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<usize> {
+        if entity.is_directory() {
+            return Err(NoctFSError::IsADirectory);
+        }
+
+        let (_, mut new_entity) = self.find_record(directory_block, entity)?;
+
+        self.check_access(&new_entity, Access::WRITE)?;
+
+        let block = new_entity.start_block;
         let data_len = data.len();
 
-        let offset_end = core::cmp::max(entity.size, data_len as u64 + offset);
+        let offset_end = core::cmp::max(new_entity.size, data_len as u64 + offset);
 
-        let target_chain_len = offset_end.div_ceil(self.bootsector.block_size as _) as usize;
+        // A file always keeps its first block, even when it's empty.
+        let target_chain_len =
+            (offset_end.div_ceil(self.bootsector.block_size as _) as usize).max(1);
 
-        self.set_chain_size(block, target_chain_len);
+        self.set_chain_size(block, target_chain_len)?;
+
+        // Blocks may be reused, so a gap left by writing past the end must be cleared.
+        if offset > new_entity.size {
+            self.zero_range(block, new_entity.size, offset)?;
+        }
 
-        let result = self.write_blocks_data(block, data, offset)?;
+        let result = self.write_contents(block, data, offset)?;
 
         // Update file metadata
 
-        let ent_offset = self.get_entity_offset(directory_block, entity).unwrap();
-        let mut new_entity = entity.clone();
+        let old_entity = new_entity.clone();
+        let now = self.clock.now();
 
         new_entity.size = offset_end;
+        new_entity.times.modified = now;
+        new_entity.times.changed = now;
 
-        self.write_blocks_data(directory_block, &new_entity.as_raw(), ent_offset as _)?;
+        self.overwrite_entity_header(directory_block, &old_entity, &new_entity)?;
 
         Ok(result)
     }
 
+    /// Sets the file's size to `new_len`, returning the updated entity.
+    ///
+    /// Shrinking frees the blocks past the new end and zeroes the rest of the last block,
+    /// so growing the file again never brings the old contents back.
+    /// Growing fills the new space with zeroes.
+    pub fn truncate(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        new_len: u64,
+    ) -> Result<Entity> {
+        self.atomic(|fs| fs.truncate_inner(directory_block, entity, new_len))
+    }
+
+    fn truncate_inner(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        new_len: u64,
+    ) -> Result<Entity> {
+        if entity.is_directory() {
+            return Err(NoctFSError::IsADirectory);
+        }
+
+        let (_, mut new_entity) = self.find_record(directory_block, entity)?;
+        let old_entity = new_entity.clone();
+
+        self.check_access(&new_entity, Access::WRITE)?;
+
+        let block = new_entity.start_block;
+        let block_size = self.bootsector.block_size as u64;
+
+        // A file always keeps its first block, even when it's empty.
+        let target_chain_len = (new_len.div_ceil(block_size) as usize).max(1);
+
+        if new_len < new_entity.size {
+            self.set_chain_size(block, target_chain_len)?;
+
+            let block_end = new_len.next_multiple_of(block_size).max(block_size);
+
+            self.zero_range(block, new_len, core::cmp::min(block_end, new_entity.size))?;
+        } else if new_len > new_entity.size {
+            self.set_chain_size(block, target_chain_len)?;
+            self.zero_range(block, new_entity.size, new_len)?;
+        }
+
+        let now = self.clock.now();
+
+        new_entity.size = new_len;
+        new_entity.times.modified = now;
+        new_entity.times.changed = now;
+
+        self.overwrite_entity_header(directory_block, &old_entity, &new_entity)?;
+
+        Ok(new_entity)
+    }
+
+    /// Replaces the entity's record with `new_entity`.
+    ///
+    /// A record of the same size is overwritten in place. Otherwise, e.g. for a record written
+    /// before timestamps existed, the new record is appended and the old one is removed.
+    ///
+    /// Other hard links to the same chain get the new metadata as well.
     pub fn overwrite_entity_header(
         &mut self,
         directory_block: BlockAddress,
         entity: &Entity,
         new_entity: &Entity,
-    ) -> Option<()> {
+    ) -> Result<()> {
+        self.atomic(|fs| {
+            fs.replace_record(directory_block, entity, new_entity)?;
+
+            fs.sync_links(directory_block, new_entity)
+        })
+    }
+
+    /// Replaces a single record, leaving other links alone.
+    pub(crate) fn replace_record(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        new_entity: &Entity,
+    ) -> Result<()> {
         let ent_offset = self.get_entity_offset(directory_block, entity)?;
+        let raw_entity = new_entity.as_raw();
+
+        if self.record_size_at(directory_block, ent_offset)? == raw_entity.len() {
+            self.write_blocks_data(directory_block, &raw_entity, ent_offset as _)?;
+        } else {
+            // Appending may need another block: if there's none, the old record is still there.
+            // Records are packed, so the old one still comes first if both have the same name.
+            self.write_entity(directory_block, new_entity)?;
+            self.remove_record(directory_block, entity)?;
+        }
+
+        Ok(())
+    }
+
+    /// Size of the on-disk record at `offset`, including its header size field.
+    fn record_size_at(&mut self, directory_block: BlockAddress, offset: usize) -> Result<usize> {
+        let mut header_size = [0u8; 4];
+
+        self.read_blocks_data(directory_block, &mut header_size, offset as _)?;
+
+        Ok(u32::from_le_bytes(header_size) as usize + 4)
+    }
+
+    /// Sets the entity's access time to now, leaving the other timestamps alone.
+    pub(crate) fn mark_accessed(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<Entity> {
+        let (_, current) = self.find_record(directory_block, entity)?;
+
+        let mut new_entity = current.clone();
+        new_entity.times.accessed = self.clock.now();
+
+        self.overwrite_entity_header(directory_block, &current, &new_entity)?;
+
+        Ok(new_entity)
+    }
+
+    /// Sets the modification and change times of the directory at `directory_block` to now,
+    /// after an entry was added to or removed from it.
+    pub(crate) fn touch_directory(&mut self, directory_block: BlockAddress) -> Result<()> {
+        let root = self.bootsector.first_root_entity_block;
+
+        let (parent, current) = if directory_block == root {
+            (root, self.get_root_entity()?)
+        } else {
+            let parent = self.find_entity(directory_block, "..")?.start_block;
+
+            (
+                parent,
+                self.get_entity_by_parent_and_block(parent, directory_block)?,
+            )
+        };
+
+        let now = self.clock.now();
 
-        self.write_blocks_data(directory_block, &new_entity.as_raw(), ent_offset as _)
-            .unwrap();
+        let mut new_entity = current.clone();
+        new_entity.times.modified = now;
+        new_entity.times.changed = now;
 
-        Some(())
+        self.update_record(parent, &current, &new_entity)
+    }
+
+    /// Sets the entity's timestamps, e.g. to preserve them when copying files in.
+    pub fn set_times(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        times: Timestamps,
+    ) -> Result<Entity> {
+        let current = self.current_record(directory_block, entity)?;
+
+        let mut new_entity = current.clone();
+        new_entity.times = times;
+
+        self.update_record(directory_block, &current, &new_entity)?;
+
+        Ok(new_entity)
+    }
+
+    /// The on-disk copy of the entity's record. The root, which has no record,
+    /// is reported through its `.` record.
+    pub(crate) fn current_record(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<Entity> {
+        if self.is_root_entity(entity) {
+            return self.get_root_entity();
+        }
+
+        self.find_record(directory_block, entity)
+            .map(|(_, current)| current)
+    }
+
+    /// Rewrites a record obtained from `current_record`.
+    pub(crate) fn update_record(
+        &mut self,
+        directory_block: BlockAddress,
+        current: &Entity,
+        new_entity: &Entity,
+    ) -> Result<()> {
+        if !self.is_root_entity(current) {
+            return self.overwrite_entity_header(directory_block, current, new_entity);
+        }
+
+        let root_block = current.start_block;
+        let this = self.find_entity(root_block, ".")?;
+
+        let mut new_this = new_entity.clone();
+        new_this.name = ".".to_string();
+
+        self.overwrite_entity_header(root_block, &this, &new_this)
+    }
+
+    pub(crate) fn is_root_entity(&self, entity: &Entity) -> bool {
+        entity.is_directory() && entity.start_block == self.bootsector.first_root_entity_block
     }
 
     pub fn read_contents_by_entity(
         &mut self,
+        directory_block: BlockAddress,
         entity: &Entity,
         data: &mut [u8],
         offset: u64,
-    ) -> io::Result<usize> {
-        self.read_blocks_data(entity.start_block, data, offset)
+    ) -> Result<usize> {
+        if entity.is_directory() {
+            return Err(NoctFSError::IsADirectory);
+        }
+
+        // The caller's copy may be outdated, or made up: permissions come from the record.
+        let current = self.current_record(directory_block, entity)?;
+
+        self.check_access(&current, Access::READ)?;
+
+        self.read_blocks_data(current.start_block, data, offset)
     }
 
-    pub fn list_directory(&mut self, directory_block: BlockAddress) -> Vec<Entity> {
-        let mut ents: Vec<Entity> = vec![];
+    pub fn list_directory(&mut self, directory_block: BlockAddress) -> Result<Vec<Entity>> {
+        self.check_directory_access(directory_block, Access::READ)?;
 
-        let data = self.read_chain_data_vec(directory_block);
-        let mut index = 0usize;
+        Ok(self
+            .read_records(directory_block)?
+            .into_iter()
+            .map(|(_, entity)| entity)
+            .collect())
+    }
 
-        while index < data.len() {
-            let header_size = u32::from_le_bytes(data[index..index + 4].try_into().unwrap());
+    /// Removes the entity's record from the directory, leaving its data chain alone.
+    fn remove_record(&mut self, directory_block: BlockAddress, entity: &Entity) -> Result<()> {
+        let mut data = self.read_chain_data_vec(directory_block)?;
+        let (off, _) = self.find_record(directory_block, entity)?;
+        let entity_size = u32::from_le_bytes(data[off..off + 4].try_into().unwrap()) as usize + 4;
+        let off_end = off + entity_size;
 
-            if header_size == 0 {
-                break;
+        data.copy_within(off_end.., off);
+
+        // Don't leave a stale copy of the last record behind.
+        let data_len = data.len();
+        data[data_len - entity_size..].fill(0);
+
+        self.write_blocks_data(directory_block, data.as_slice(), 0)?;
+
+        Ok(())
+    }
+
+    pub fn delete_entity(&mut self, directory_block: BlockAddress, entity: &Entity) -> Result<()> {
+        self.atomic(|fs| fs.delete_entity_inner(directory_block, entity))
+    }
+
+    fn delete_entity_inner(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<()> {
+        self.check_directory_access(directory_block, Access::WRITE | Access::EXECUTE)?;
+
+        self.remove_record(directory_block, entity)?;
+        self.touch_directory(directory_block)?;
+        self.count_entity(entity, false);
+
+        // Other hard links still need the data.
+        if !self.release_link(entity.start_block, directory_block) {
+            return self.write_through_links();
+        }
+
+        self.free_blocks(entity.start_block)
+    }
+
+    pub fn delete_file(&mut self, directory_block: BlockAddress, entity: &Entity) -> Result<()> {
+        if entity.is_directory() {
+            return Err(NoctFSError::IsADirectory);
+        }
+
+        self.delete_entity(directory_block, entity)
+    }
+
+    /// Removes an empty directory.
+    pub fn remove_dir(&mut self, directory_block: BlockAddress, entity: &Entity) -> Result<()> {
+        if !entity.is_directory() {
+            return Err(NoctFSError::NotADirectory);
+        }
+
+        if entity.name == "." || entity.name == ".." {
+            return Err(NoctFSError::InvalidName);
+        }
+
+        if !self.is_directory_empty(entity.start_block)? {
+            return Err(NoctFSError::DirectoryNotEmpty);
+        }
+
+        self.delete_entity(directory_block, entity)
+    }
+
+    /// Removes a directory with everything inside it.
+    ///
+    /// Descendants are removed depth-first, so every chain and record is freed,
+    /// and a directory is only deleted once it's empty.
+    pub fn remove_dir_all(&mut self, directory_block: BlockAddress, entity: &Entity) -> Result<()> {
+        if !entity.is_directory() {
+            return Err(NoctFSError::NotADirectory);
+        }
+
+        if entity.name == "." || entity.name == ".." {
+            return Err(NoctFSError::InvalidName);
+        }
+
+        let mut stack = vec![(directory_block, entity.clone())];
+
+        while let Some((parent, directory)) = stack.last().cloned() {
+            let mut has_subdirectories = false;
+
+            for child in self.list_directory(directory.start_block)? {
+                if child.name == "." || child.name == ".." {
+                    continue;
+                }
+
+                if !child.is_directory() {
+                    self.delete_entity(directory.start_block, &child)?;
+                    continue;
+                }
+
+                // A directory linking back to one of its ancestors would make us loop forever.
+                if stack
+                    .iter()
+                    .any(|(_, ancestor)| ancestor.start_block == child.start_block)
+                {
+                    return Err(NoctFSError::Corrupted {
+                        block: child.start_block,
+                    });
+                }
+
+                stack.push((directory.start_block, child));
+                has_subdirectories = true;
             }
 
-            let entity = Entity::from_raw(&data[index..]);
+            // Come back to this directory once its subdirectories are gone.
+            if !has_subdirectories {
+                stack.pop();
+                self.delete_entity(parent, &directory)?;
+            }
+        }
+
+        Ok(())
+    }
 
-            ents.push(entity);
+    /// Returns `true` if the directory has no entries besides `.` and `..`.
+    fn is_directory_empty(&mut self, directory_block: BlockAddress) -> Result<bool> {
+        Ok(self
+            .read_records(directory_block)?
+            .iter()
+            .all(|(_, entity)| entity.name == "." || entity.name == ".."))
+    }
 
-            index += header_size as usize + 4;
+    /// Returns `true` if `directory_block` is `ancestor` or lies somewhere below it.
+    ///
+    /// Walks the `..` records up to the root, which is its own parent.
+    fn is_within(&mut self, directory_block: BlockAddress, ancestor: BlockAddress) -> Result<bool> {
+        let root = self.bootsector.first_root_entity_block;
+        let mut current = directory_block;
+
+        // A well-formed tree can't be deeper than the number of blocks.
+        for _ in 0..=self.bootsector.block_map_count {
+            if current == ancestor {
+                return Ok(true);
+            }
+
+            if current == root {
+                return Ok(false);
+            }
+
+            current = self.find_entity(current, "..")?.start_block;
         }
 
-        ents
+        Err(NoctFSError::Corrupted {
+            block: directory_block,
+        })
     }
 
-    pub fn delete_entity(&mut self, directory_block: BlockAddress, entity: &Entity) {
-        let mut data = self.read_chain_data_vec(directory_block);
-        let off = self.get_entity_offset(directory_block, entity).unwrap();
-        let entity_size = entity.fact_size() as usize;
-        let off_end = off + entity_size;
+    /// Moves `entity` from the directory at `src_dir` into `dst_dir` under `new_name`.
+    ///
+    /// Only directory records are rewritten; the data chain stays where it is.
+    /// An existing file (or empty directory) named `new_name` is replaced, and its chain is freed.
+    /// When the replaced record has the same size as the new one, it's overwritten in place,
+    /// so the target name never goes missing.
+    pub fn rename<T: ToString>(
+        &mut self,
+        src_dir: BlockAddress,
+        entity: &Entity,
+        dst_dir: BlockAddress,
+        new_name: T,
+    ) -> Result<Entity> {
+        let new_name = new_name.to_string();
 
-        data.copy_within(off_end.., off);
+        self.atomic(|fs| fs.rename_inner(src_dir, entity, dst_dir, new_name))
+    }
+
+    fn rename_inner(
+        &mut self,
+        src_dir: BlockAddress,
+        entity: &Entity,
+        dst_dir: BlockAddress,
+        new_name: String,
+    ) -> Result<Entity> {
+        validate_name(&new_name)?;
 
-        self.free_blocks(entity.start_block);
+        if entity.name == "." || entity.name == ".." {
+            return Err(NoctFSError::InvalidName);
+        }
+
+        let (_, current) = self.find_record(src_dir, entity)?;
 
-        self.write_blocks_data(directory_block, data.as_slice(), 0)
-            .unwrap();
+        self.check_directory_access(src_dir, Access::WRITE | Access::EXECUTE)?;
+        self.check_directory_access(dst_dir, Access::WRITE | Access::EXECUTE)?;
+
+        // The `..` record of a directory changing parents gets rewritten.
+        if current.is_directory() && src_dir != dst_dir {
+            self.check_access(&current, Access::WRITE)?;
+        }
+
+        if current.is_directory() && self.is_within(dst_dir, current.start_block)? {
+            return Err(NoctFSError::InvalidMove);
+        }
+
+        let mut moved = current.clone();
+        moved.name = new_name;
+        moved.times.changed = self.clock.now();
+
+        if src_dir == dst_dir && current.name == moved.name {
+            return Ok(current);
+        }
+
+        let target = match self.find_entity(dst_dir, &moved.name) {
+            Ok(target) => Some(target),
+            Err(NoctFSError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(target) = &target {
+            // Both names already lead to the same data.
+            if target.start_block == current.start_block {
+                return Ok(current);
+            }
+
+            match (current.is_directory(), target.is_directory()) {
+                (false, true) => return Err(NoctFSError::IsADirectory),
+                (true, false) => return Err(NoctFSError::NotADirectory),
+                (true, true) if !self.is_directory_empty(target.start_block)? => {
+                    return Err(NoctFSError::DirectoryNotEmpty)
+                }
+                _ => {}
+            }
+        }
+
+        // The new record goes in first: if we stop halfway, the entity is reachable
+        // under both names instead of neither.
+        match &target {
+            Some(target) => self.overwrite_entity_header(dst_dir, target, &moved)?,
+            None if src_dir == dst_dir => {
+                self.overwrite_entity_header(src_dir, &current, &moved)?
+            }
+            None => self.write_entity(dst_dir, &moved)?,
+        }
+
+        // Renaming within a directory already replaced the old record.
+        if target.is_some() || src_dir != dst_dir {
+            self.remove_record(src_dir, &current)?;
+        }
+
+        if current.is_directory() && src_dir != dst_dir {
+            let parent = self.find_entity(current.start_block, "..")?;
+            let mut new_parent = parent.clone();
+            new_parent.start_block = dst_dir;
+
+            self.overwrite_entity_header(current.start_block, &parent, &new_parent)?;
+        }
+
+        self.touch_directory(src_dir)?;
+
+        if src_dir != dst_dir {
+            self.touch_directory(dst_dir)?;
+        }
+
+        if src_dir != dst_dir {
+            self.move_link(current.start_block, src_dir, dst_dir);
+        }
+
+        if let Some(target) = target {
+            self.count_entity(&target, false);
+
+            if self.release_link(target.start_block, dst_dir) {
+                self.free_blocks(target.start_block)?;
+            }
+        }
+
+        self.write_through_links()?;
+
+        Ok(moved)
     }
+}
 
-    pub fn delete_file(&mut self, directory_block: BlockAddress, entity: &Entity) {
-        if entity.is_directory() {
+impl Drop for NoctFS<'_> {
+    fn drop(&mut self) {
+        // A half-loaded volume would be synced from incomplete metadata.
+        if !self.mounted {
             return;
         }
 
-        self.delete_entity(directory_block, entity);
+        // Best effort: use `unmount` to find out whether this succeeded.
+        let _ = self.release();
     }
 }
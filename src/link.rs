@@ -0,0 +1,318 @@
+//! Hard links.
+//!
+//! Every record of a file points to the file's chain, so the chain has to outlive all but the
+//! last of them. Chains with more than one record are listed in the link table, along with the
+//! directory of each record (a directory holding two links is listed twice). Chains missing from
+//! the table have exactly one record.
+//!
+//! The table is kept in memory and written to its own chain on sync, as pairs of:
+//!  [0..8]  (8 bytes) - Start block of the linked chain
+//!  [8..16] (8 bytes) - Directory block holding one of its records
+//!
+//! up to the end of the chain or the first pair with a zero start block.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    access::Access, bootsector::RoCompatFeatures, entity::Entity, BlockAddress, NoctFS,
+    NoctFSError, Result,
+};
+
+pub(crate) type LinkTable = BTreeMap<BlockAddress, Vec<BlockAddress>>;
+
+const PAIR_SIZE: usize = 16;
+
+impl NoctFS<'_> {
+    pub(crate) fn load_link_table(&mut self) -> Result<()> {
+        let table_block = self.bootsector.link_table_block;
+
+        if table_block == 0 {
+            return Ok(());
+        }
+
+        let data = self.read_chain_data_vec(table_block)?;
+
+        for pair in data.chunks_exact(PAIR_SIZE) {
+            let start_block = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let directory_block = u64::from_le_bytes(pair[8..].try_into().unwrap());
+
+            if start_block == 0 {
+                break;
+            }
+
+            self.links
+                .entry(start_block)
+                .or_default()
+                .push(directory_block);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the link table back if it changed, resizing or freeing its chain as needed.
+    pub(crate) fn write_link_table(&mut self) -> Result<()> {
+        if !self.links_dirty {
+            return Ok(());
+        }
+
+        let mut data: Vec<u8> = self
+            .links
+            .iter()
+            .flat_map(|(start_block, directories)| {
+                directories.iter().flat_map(move |directory_block| {
+                    start_block
+                        .to_le_bytes()
+                        .into_iter()
+                        .chain(directory_block.to_le_bytes())
+                })
+            })
+            .collect();
+
+        let table_block = self.bootsector.link_table_block;
+
+        if data.is_empty() {
+            if table_block != 0 {
+                self.bootsector.link_table_block = 0;
+                self.bootsector
+                    .ro_compat_features
+                    .remove(RoCompatFeatures::LINK_TABLE);
+                self.write_bootsector()?;
+
+                self.free_blocks(table_block)?;
+            }
+
+            self.links_dirty = false;
+
+            return Ok(());
+        }
+
+        let blocks = data.len().div_ceil(self.block_size());
+
+        let table_block = self.grow_link_table(blocks)?;
+
+        // A shorter table gives its last blocks back.
+        self.set_chain_size(table_block, blocks)?;
+
+        // Clear whatever is left of a longer table.
+        data.resize(blocks * self.block_size(), 0);
+
+        self.write_blocks_data(table_block, &data, 0)?;
+
+        self.links_dirty = false;
+
+        Ok(())
+    }
+
+    /// Makes the table's chain at least `blocks` long, allocating it if there's none yet.
+    /// Blocks it gains hold zeroes, so they read as the end of the table.
+    fn grow_link_table(&mut self, blocks: usize) -> Result<BlockAddress> {
+        let block_size = self.block_size();
+        let table_block = self.bootsector.link_table_block;
+
+        if table_block == 0 {
+            let table_block = self.allocate_blocks(blocks as _)?;
+            self.write_blocks_data(table_block, &vec![0; blocks * block_size], 0)?;
+
+            // Without a journal, the chain has to be on the device before the bootsector.
+            self.write_back_pages()?;
+
+            self.bootsector.link_table_block = table_block;
+            self.bootsector
+                .ro_compat_features
+                .insert(RoCompatFeatures::LINK_TABLE);
+            self.write_bootsector()?;
+
+            return Ok(table_block);
+        }
+
+        let current = self.get_chain(table_block)?.len();
+
+        if current < blocks {
+            self.set_chain_size(table_block, blocks)?;
+            self.write_blocks_data(
+                table_block,
+                &vec![0; (blocks - current) * block_size],
+                (current * block_size) as u64,
+            )?;
+        }
+
+        Ok(table_block)
+    }
+
+    /// Number of records pointing to the entity's chain.
+    pub fn link_count(&self, entity: &Entity) -> usize {
+        self.links
+            .get(&entity.start_block)
+            .map_or(1, |directories| directories.len())
+    }
+
+    /// Creates another record named `name` in `dst_dir` for the file in `src_dir`.
+    ///
+    /// Both records share the data chain and metadata; the chain is only freed
+    /// once the last of them is deleted. Directories can't be linked.
+    pub fn link<T: ToString>(
+        &mut self,
+        src_dir: BlockAddress,
+        existing: &Entity,
+        dst_dir: BlockAddress,
+        name: T,
+    ) -> Result<Entity> {
+        let name = name.to_string();
+
+        self.atomic(|fs| fs.link_inner(src_dir, existing, dst_dir, name))
+    }
+
+    fn link_inner(
+        &mut self,
+        src_dir: BlockAddress,
+        existing: &Entity,
+        dst_dir: BlockAddress,
+        name: String,
+    ) -> Result<Entity> {
+        if existing.is_directory() {
+            return Err(NoctFSError::IsADirectory);
+        }
+
+        let (_, current) = self.find_record(src_dir, existing)?;
+
+        self.check_directory_access(dst_dir, Access::WRITE | Access::EXECUTE)?;
+        self.check_new_name(dst_dir, &name)?;
+
+        // The table is only written back later, when running out of space would leave the new
+        // record unaccounted for. Without a journal, that can't be undone, so make room first.
+        let pairs: usize = self.links.values().map(Vec::len).sum();
+        let new_pairs = match self.links.contains_key(&current.start_block) {
+            true => 1,
+            false => 2,
+        };
+
+        self.grow_link_table(((pairs + new_pairs) * PAIR_SIZE).div_ceil(self.block_size()))?;
+
+        let mut linked = current.clone();
+        linked.name = name;
+
+        self.write_entity(dst_dir, &linked)?;
+        self.touch_directory(dst_dir)?;
+        self.count_entity(&linked, true);
+
+        self.links
+            .entry(current.start_block)
+            .or_insert_with(|| vec![src_dir])
+            .push(dst_dir);
+        self.links_dirty = true;
+
+        self.write_through_links()?;
+
+        Ok(linked)
+    }
+
+    /// With the `sync` mount option, writes the link table right after it changes.
+    pub(crate) fn write_through_links(&mut self) -> Result<()> {
+        match self.options.sync {
+            true => self.write_link_table(),
+            false => Ok(()),
+        }
+    }
+
+    /// Forgets the record in `directory_block` pointing to `start_block`.
+    /// Returns `true` if it was the last one, so the chain can be freed.
+    pub(crate) fn release_link(
+        &mut self,
+        start_block: BlockAddress,
+        directory_block: BlockAddress,
+    ) -> bool {
+        let Some(directories) = self.links.get_mut(&start_block) else {
+            return true;
+        };
+
+        // A table that lost track of the directory still has one link too many.
+        let index = directories
+            .iter()
+            .position(|&block| block == directory_block)
+            .unwrap_or(0);
+
+        directories.remove(index);
+
+        if directories.len() < 2 {
+            self.links.remove(&start_block);
+        }
+
+        self.links_dirty = true;
+
+        false
+    }
+
+    /// Notes that a record pointing to `start_block` moved to another directory.
+    pub(crate) fn move_link(
+        &mut self,
+        start_block: BlockAddress,
+        from: BlockAddress,
+        to: BlockAddress,
+    ) {
+        let Some(directories) = self.links.get_mut(&start_block) else {
+            return;
+        };
+
+        if let Some(directory) = directories.iter_mut().find(|block| **block == from) {
+            *directory = to;
+            self.links_dirty = true;
+        }
+    }
+
+    /// Copies the metadata of `new_entity` to the other records of its chain.
+    pub(crate) fn sync_links(
+        &mut self,
+        directory_block: BlockAddress,
+        new_entity: &Entity,
+    ) -> Result<()> {
+        let Some(directories) = self.links.get(&new_entity.start_block) else {
+            return Ok(());
+        };
+
+        let mut directories = directories.clone();
+        directories.sort_unstable();
+        directories.dedup();
+
+        for directory in directories {
+            let siblings: Vec<Entity> = self
+                .read_records(directory)?
+                .into_iter()
+                .map(|(_, entity)| entity)
+                .filter(|entity| {
+                    entity.start_block == new_entity.start_block
+                        && !(directory == directory_block && entity.name == new_entity.name)
+                })
+                .collect();
+
+            for sibling in siblings {
+                let mut updated = new_entity.clone();
+                updated.name = sibling.name.clone();
+
+                self.replace_record(directory, &sibling, &updated)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Table entries as the checker sees them.
+    pub(crate) fn link_table(&self) -> &LinkTable {
+        &self.links
+    }
+
+    /// Replaces the entry of `start_block`, e.g. with the links the checker actually found.
+    pub(crate) fn set_links(&mut self, start_block: BlockAddress, directories: Vec<BlockAddress>) {
+        if directories.len() < 2 {
+            self.links.remove(&start_block);
+        } else {
+            self.links.insert(start_block, directories);
+        }
+
+        self.links_dirty = true;
+    }
+}
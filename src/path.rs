@@ -0,0 +1,231 @@
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+};
+
+use crate::{
+    access::Access, entity::Entity, symlink::MAX_SYMLINK_FOLLOWS, BlockAddress, NoctFS,
+    NoctFSError, Result,
+};
+
+/// Splits a path into its non-empty components.
+///
+/// Both `/a/b` and `a/b` yield the same components, since every path is resolved from the root.
+fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|component| !component.is_empty())
+}
+
+/// Splits a path into its parent path and the last component.
+fn split_last(path: &str) -> Result<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+
+    let (parent, name) = match path.rfind('/') {
+        Some(index) => (&path[..index], &path[index + 1..]),
+        None => ("", path),
+    };
+
+    if name.is_empty() {
+        return Err(NoctFSError::InvalidName);
+    }
+
+    Ok((parent, name))
+}
+
+impl NoctFS<'_> {
+    /// Resolves `path` and returns the block of the directory holding the entity, and the entity itself.
+    ///
+    /// `.` and `..` are resolved through the records written by `create_directory`.
+    /// The root has no record of its own, so it's reported as its own parent.
+    fn resolve(&mut self, path: &str) -> Result<(BlockAddress, Entity)> {
+        let root = self.get_root_entity()?;
+
+        self.resolve_from(root, path, true)
+    }
+
+    /// Like `resolve`, but a symbolic link in the last component is returned instead of followed.
+    fn resolve_nofollow(&mut self, path: &str) -> Result<(BlockAddress, Entity)> {
+        let root = self.get_root_entity()?;
+
+        self.resolve_from(root, path, false)
+    }
+
+    /// Resolves `path` relative to the directory `start`.
+    ///
+    /// Symbolic links along the way are followed, relative ones from the directory holding the link.
+    /// A link in the last component is only followed if `follow` is set.
+    pub(crate) fn resolve_from(
+        &mut self,
+        start: Entity,
+        path: &str,
+        follow: bool,
+    ) -> Result<(BlockAddress, Entity)> {
+        let mut parent = start.start_block;
+        let mut current = start;
+
+        let mut pending: VecDeque<String> = components(path).map(String::from).collect();
+        let mut followed = 0;
+
+        while let Some(component) = pending.pop_front() {
+            if !current.is_directory() {
+                return Err(NoctFSError::NotADirectory);
+            }
+
+            self.check_access(&current, Access::EXECUTE)?;
+
+            let next = self.find_entity(current.start_block, &component)?;
+
+            if next.is_symlink() && (follow || !pending.is_empty()) {
+                followed += 1;
+
+                if followed > MAX_SYMLINK_FOLLOWS {
+                    return Err(NoctFSError::SymlinkLoop);
+                }
+
+                let target = self.read_link(&next)?;
+
+                if target.starts_with('/') {
+                    current = self.get_root_entity()?;
+                    parent = current.start_block;
+                }
+
+                for component in components(&target).rev() {
+                    pending.push_front(component.to_string());
+                }
+
+                continue;
+            }
+
+            parent = current.start_block;
+            current = next;
+        }
+
+        Ok((parent, current))
+    }
+
+    /// Resolves `path` to a directory and returns its start block.
+    fn resolve_directory(&mut self, path: &str) -> Result<BlockAddress> {
+        let (_, entity) = self.resolve(path)?;
+
+        if !entity.is_directory() {
+            return Err(NoctFSError::NotADirectory);
+        }
+
+        Ok(entity.start_block)
+    }
+
+    /// Finds the entity at `path`.
+    pub fn lookup(&mut self, path: &str) -> Result<Entity> {
+        self.resolve(path).map(|(_, entity)| entity)
+    }
+
+    /// Finds the entity at `path` together with the block of its parent directory,
+    /// which is what `write_contents_by_entity` and `delete_entity` expect.
+    pub fn open_path(&mut self, path: &str) -> Result<(BlockAddress, Entity)> {
+        self.resolve(path)
+    }
+
+    /// Like `open_path`, but returns a symbolic link at `path` itself rather than its target.
+    pub fn open_path_nofollow(&mut self, path: &str) -> Result<(BlockAddress, Entity)> {
+        self.resolve_nofollow(path)
+    }
+
+    /// Creates an empty file at `path`. The parent directory must exist.
+    pub fn create_file_at(&mut self, path: &str) -> Result<Entity> {
+        let (parent, name) = split_last(path)?;
+        let directory_block = self.resolve_directory(parent)?;
+
+        self.create_file(directory_block, name)
+    }
+
+    /// Creates a symbolic link at `path` pointing to `target`. The parent directory must exist.
+    pub fn create_symlink_at(&mut self, path: &str, target: &str) -> Result<Entity> {
+        let (parent, name) = split_last(path)?;
+        let directory_block = self.resolve_directory(parent)?;
+
+        self.create_symlink(directory_block, name, target)
+    }
+
+    /// Creates a hard link at `new_path` to the file at `existing`. The parent directory must exist.
+    ///
+    /// A symbolic link at `existing` is linked itself, not its target.
+    pub fn link_path(&mut self, existing: &str, new_path: &str) -> Result<Entity> {
+        let (src_dir, entity) = self.resolve_nofollow(existing)?;
+
+        let (parent, name) = split_last(new_path)?;
+        let dst_dir = self.resolve_directory(parent)?;
+
+        self.link(src_dir, &entity, dst_dir, name)
+    }
+
+    /// Creates the directory at `path` along with every missing parent.
+    ///
+    /// Existing directories along the way are reused.
+    pub fn mkdir_p(&mut self, path: &str) -> Result<Entity> {
+        let mut current = self.get_root_entity()?;
+
+        for component in components(path) {
+            if !current.is_directory() {
+                return Err(NoctFSError::NotADirectory);
+            }
+
+            self.check_access(&current, Access::EXECUTE)?;
+
+            current = match self.find_entity(current.start_block, component) {
+                Ok(entity) if entity.is_symlink() => self.resolve_from(current, component, true)?.1,
+                Ok(entity) => entity,
+                Err(NoctFSError::NotFound) => {
+                    self.create_directory(current.start_block, component)?
+                }
+                Err(e) => return Err(e),
+            };
+        }
+
+        if !current.is_directory() {
+            return Err(NoctFSError::AlreadyExists);
+        }
+
+        Ok(current)
+    }
+
+    /// Removes the file or empty directory at `path`.
+    pub fn remove_path(&mut self, path: &str) -> Result<()> {
+        let (directory_block, entity) = self.resolve_removable(path)?;
+
+        if entity.is_directory() {
+            self.remove_dir(directory_block, &entity)
+        } else {
+            self.delete_file(directory_block, &entity)
+        }
+    }
+
+    /// Removes the directory at `path` with everything inside it.
+    pub fn remove_dir_all_at(&mut self, path: &str) -> Result<()> {
+        let (directory_block, entity) = self.resolve_removable(path)?;
+
+        self.remove_dir_all(directory_block, &entity)
+    }
+
+    /// Resolves a path whose last component can be unlinked from its parent.
+    ///
+    /// The root and the `.`/`..` records can't be. Symbolic links aren't followed,
+    /// so the link itself is what gets removed or moved.
+    fn resolve_removable(&mut self, path: &str) -> Result<(BlockAddress, Entity)> {
+        let (_, name) = split_last(path)?;
+
+        if name == "." || name == ".." {
+            return Err(NoctFSError::InvalidName);
+        }
+
+        self.resolve_nofollow(path)
+    }
+
+    /// Moves the entity at `from` to `to`, replacing whatever file or empty directory is there.
+    pub fn rename_path(&mut self, from: &str, to: &str) -> Result<Entity> {
+        let (src_dir, entity) = self.resolve_removable(from)?;
+
+        let (parent, new_name) = split_last(to)?;
+        let dst_dir = self.resolve_directory(parent)?;
+
+        self.rename(src_dir, &entity, dst_dir, new_name)
+    }
+}
@@ -0,0 +1,115 @@
+use alloc::{collections::BTreeSet, vec};
+
+use crate::{entity::Entity, NoctFS, Result, MAX_NAME_LENGTH};
+
+/// Usage of the volume, as returned by [`NoctFS::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub block_size: usize,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    /// Blocks taken by entities and filesystem metadata, including the reserved block 0.
+    pub used_blocks: u64,
+    /// Number of file records. A file with several hard links is counted once per link.
+    pub files: u64,
+    /// Number of directories, including the root.
+    pub directories: u64,
+    pub symlinks: u64,
+    pub max_name_length: usize,
+    /// Size of the data zone in bytes.
+    pub volume_size: u64,
+}
+
+/// Number of records of each kind.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct EntityCounts {
+    files: u64,
+    directories: u64,
+    symlinks: u64,
+}
+
+impl EntityCounts {
+    fn counter(&mut self, entity: &Entity) -> &mut u64 {
+        if entity.is_directory() {
+            &mut self.directories
+        } else if entity.is_symlink() {
+            &mut self.symlinks
+        } else {
+            &mut self.files
+        }
+    }
+}
+
+impl NoctFS<'_> {
+    /// Returns block usage and entity counts of the volume.
+    ///
+    /// Free blocks come from the free block bitmap. Entities are counted by walking the tree
+    /// on the first call after mounting, and kept up to date by every later change.
+    pub fn stats(&mut self) -> Result<Stats> {
+        let counts = match self.entity_counts {
+            Some(counts) => counts,
+            None => {
+                let counts = self.count_entities()?;
+                self.entity_counts = Some(counts);
+                counts
+            }
+        };
+
+        let block_size = self.block_size();
+        let total_blocks = self.bootsector.block_map_count as u64;
+        let free_blocks = self.free_map.free_count();
+
+        Ok(Stats {
+            block_size,
+            total_blocks,
+            free_blocks,
+            used_blocks: total_blocks - free_blocks,
+            files: counts.files,
+            directories: counts.directories,
+            symlinks: counts.symlinks,
+            max_name_length: MAX_NAME_LENGTH,
+            volume_size: total_blocks * block_size as u64,
+        })
+    }
+
+    /// Accounts for a record that was just added (or, with `added` unset, removed).
+    pub(crate) fn count_entity(&mut self, entity: &Entity, added: bool) {
+        if let Some(counts) = &mut self.entity_counts {
+            let counter = counts.counter(entity);
+
+            if added {
+                *counter += 1;
+            } else {
+                *counter = counter.saturating_sub(1);
+            }
+        }
+    }
+
+    fn count_entities(&mut self) -> Result<EntityCounts> {
+        let root = self.bootsector.first_root_entity_block;
+
+        let mut counts = EntityCounts {
+            directories: 1,
+            ..EntityCounts::default()
+        };
+        let mut visited = BTreeSet::from([root]);
+        let mut pending = vec![root];
+
+        while let Some(directory_block) = pending.pop() {
+            for (_, entity) in self.read_records(directory_block)? {
+                if entity.name == "." || entity.name == ".." {
+                    continue;
+                }
+
+                *counts.counter(&entity) += 1;
+
+                // A directory reachable twice would be counted, and walked, forever.
+                if entity.is_directory() && visited.insert(entity.start_block) {
+                    pending.push(entity.start_block);
+                }
+            }
+        }
+
+        Ok(counts)
+    }
+}
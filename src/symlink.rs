@@ -0,0 +1,100 @@
+use alloc::{
+    string::{String, ToString},
+    vec,
+};
+
+use crate::{
+    access::Access,
+    entity::{Entity, Timestamps},
+    BlockAddress, NoctFS, NoctFSError, Result,
+};
+
+/// How many symbolic links a single path resolution may follow.
+pub const MAX_SYMLINK_FOLLOWS: usize = 40;
+/// Longest allowed symbolic link target, in bytes.
+pub const MAX_SYMLINK_TARGET_LENGTH: usize = 4095;
+
+impl NoctFS<'_> {
+    /// Creates a symbolic link named `name` in the directory at `directory_block`.
+    ///
+    /// The target is stored as-is in the link's data chain and isn't required to exist.
+    pub fn create_symlink<T: ToString>(
+        &mut self,
+        directory_block: BlockAddress,
+        name: T,
+        target: &str,
+    ) -> Result<Entity> {
+        let name = name.to_string();
+
+        self.atomic(|fs| fs.create_symlink_inner(directory_block, name, target))
+    }
+
+    fn create_symlink_inner(
+        &mut self,
+        directory_block: BlockAddress,
+        name: String,
+        target: &str,
+    ) -> Result<Entity> {
+        if target.is_empty() || target.len() > MAX_SYMLINK_TARGET_LENGTH || target.contains('\0') {
+            return Err(NoctFSError::InvalidName);
+        }
+
+        self.check_directory_access(directory_block, Access::WRITE | Access::EXECUTE)?;
+        self.check_new_name(directory_block, &name)?;
+
+        let block = self.allocate_bytes(target.len())?;
+        let (uid, gid) = self.new_owner();
+
+        let mut entity = Entity::symlink(name, target.len(), block);
+        entity.times = Timestamps::at(self.clock.now());
+        entity.uid = uid;
+        entity.gid = gid;
+
+        let result = self
+            .write_blocks_data(block, target.as_bytes(), 0)
+            .and_then(|_| self.write_entity(directory_block, &entity));
+
+        if let Err(e) = result {
+            self.free_blocks(block)?;
+            return Err(e);
+        }
+
+        self.touch_directory(directory_block)?;
+        self.count_entity(&entity, true);
+
+        Ok(entity)
+    }
+
+    /// Returns the target of a symbolic link.
+    pub fn read_link(&mut self, entity: &Entity) -> Result<String> {
+        if !entity.is_symlink() {
+            return Err(NoctFSError::NotASymlink);
+        }
+
+        // No valid link is longer, so the record is damaged.
+        if entity.size > MAX_SYMLINK_TARGET_LENGTH as u64 {
+            return Err(NoctFSError::Corrupted {
+                block: entity.start_block,
+            });
+        }
+
+        let mut target = vec![0u8; entity.size as usize];
+
+        self.read_blocks_data(entity.start_block, &mut target, 0)?;
+
+        String::from_utf8(target).map_err(|_| NoctFSError::Corrupted {
+            block: entity.start_block,
+        })
+    }
+
+    /// Follows the symbolic link in the directory at `directory_block` to the entity it points to.
+    pub(crate) fn follow_link(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<(BlockAddress, Entity)> {
+        let directory = self.find_entity(directory_block, ".")?;
+
+        self.resolve_from(directory, &entity.name, true)
+    }
+}
@@ -0,0 +1,151 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use crate::{access::Access, entity::Entity, BlockAddress, NoctFS, NoctFSError, Result};
+
+/// Longest allowed attribute name, in bytes.
+pub const MAX_XATTR_NAME_LENGTH: usize = 255;
+/// Largest allowed attribute value, in bytes.
+pub const MAX_XATTR_VALUE_SIZE: usize = 4096;
+/// Largest total size of all attributes of one entity, as stored in its vendor data.
+pub const MAX_XATTRS_SIZE: usize = 8192;
+
+type Attributes = BTreeMap<String, Vec<u8>>;
+
+/// Parses attributes out of the vendor data.
+///
+/// Every attribute is stored as:
+///  [0..1]         (1 byte)  - Name length
+///  [1..3]         (2 bytes) - Value length
+///  [3..3+n]       (n bytes) - Name in UTF-8
+///  [3+n..3+n+v]   (v bytes) - Value
+fn decode(mut data: &[u8]) -> Option<Attributes> {
+    let mut attributes = Attributes::new();
+
+    while !data.is_empty() {
+        let (&name_len, rest) = data.split_first()?;
+        let (value_len, rest) = rest.split_at_checked(2)?;
+        let value_len = u16::from_le_bytes(value_len.try_into().ok()?) as usize;
+
+        let (name, rest) = rest.split_at_checked(name_len as usize)?;
+        let (value, rest) = rest.split_at_checked(value_len)?;
+
+        let name = String::from_utf8(name.to_vec()).ok()?;
+
+        attributes.insert(name, value.to_vec());
+
+        data = rest;
+    }
+
+    Some(attributes)
+}
+
+fn encode(attributes: &Attributes) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    for (name, value) in attributes {
+        data.push(name.len() as u8);
+        data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(value);
+    }
+
+    data
+}
+
+impl NoctFS<'_> {
+    fn read_xattrs(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        access: Access,
+    ) -> Result<(Entity, Attributes)> {
+        let current = self.current_record(directory_block, entity)?;
+
+        self.check_access(&current, access)?;
+
+        let attributes = decode(&current.vendor_data).ok_or(NoctFSError::Corrupted {
+            block: directory_block,
+        })?;
+
+        Ok((current, attributes))
+    }
+
+    fn write_xattrs(
+        &mut self,
+        directory_block: BlockAddress,
+        current: &Entity,
+        attributes: &Attributes,
+    ) -> Result<Entity> {
+        let mut new_entity = current.clone();
+        new_entity.vendor_data = encode(attributes);
+        new_entity.times.changed = self.clock.now();
+
+        self.update_record(directory_block, current, &new_entity)?;
+
+        Ok(new_entity)
+    }
+
+    /// Sets the extended attribute `name`, replacing its previous value.
+    pub fn set_xattr(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        name: &str,
+        value: &[u8],
+    ) -> Result<Entity> {
+        if name.is_empty() || name.len() > MAX_XATTR_NAME_LENGTH || name.contains('\0') {
+            return Err(NoctFSError::InvalidName);
+        }
+
+        if value.len() > MAX_XATTR_VALUE_SIZE {
+            return Err(NoctFSError::TooLarge);
+        }
+
+        let (current, mut attributes) = self.read_xattrs(directory_block, entity, Access::WRITE)?;
+
+        attributes.insert(name.into(), value.to_vec());
+
+        if encode(&attributes).len() > MAX_XATTRS_SIZE {
+            return Err(NoctFSError::TooLarge);
+        }
+
+        self.write_xattrs(directory_block, &current, &attributes)
+    }
+
+    /// Returns the value of the extended attribute `name`.
+    pub fn get_xattr(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        name: &str,
+    ) -> Result<Vec<u8>> {
+        let (_, mut attributes) = self.read_xattrs(directory_block, entity, Access::READ)?;
+
+        attributes.remove(name).ok_or(NoctFSError::NotFound)
+    }
+
+    /// Lists the names of the entity's extended attributes, in sorted order.
+    pub fn list_xattrs(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+    ) -> Result<Vec<String>> {
+        let (_, attributes) = self.read_xattrs(directory_block, entity, Access::READ)?;
+
+        Ok(attributes.into_keys().collect())
+    }
+
+    /// Removes the extended attribute `name`.
+    pub fn remove_xattr(
+        &mut self,
+        directory_block: BlockAddress,
+        entity: &Entity,
+        name: &str,
+    ) -> Result<Entity> {
+        let (current, mut attributes) = self.read_xattrs(directory_block, entity, Access::WRITE)?;
+
+        attributes.remove(name).ok_or(NoctFSError::NotFound)?;
+
+        self.write_xattrs(directory_block, &current, &attributes)
+    }
+}
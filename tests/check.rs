@@ -0,0 +1,214 @@
+//! Consistency checker: what it finds on healthy and damaged volumes.
+
+mod common;
+
+use common::{assert_clean, root, small_volume};
+use noctfs::{check::Problem, MountOptions, NoctFS};
+
+#[test]
+fn fresh_volume_is_clean() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    let report = fs.check().unwrap();
+
+    assert!(report.is_clean(), "{report}");
+    assert_eq!(
+        (report.files, report.directories, report.symlinks),
+        (0, 1, 0)
+    );
+}
+
+#[test]
+fn populated_volume_is_clean() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let file = fs.create_file(dir.start_block, "file").unwrap();
+    fs.write_contents_by_entity(dir.start_block, &file, &[3; 2000], 0)
+        .unwrap();
+    fs.link(dir.start_block, &file, root, "link").unwrap();
+    fs.create_symlink(root, "symlink", "dir/file").unwrap();
+
+    let report = fs.check().unwrap();
+
+    assert!(report.is_clean(), "{report}");
+    assert_eq!(
+        (report.files, report.directories, report.symlinks),
+        (2, 2, 1)
+    );
+
+    // And so it stays once everything is on the device.
+    fs.unmount().unwrap();
+
+    assert_clean(&mut NoctFS::new(&mut device).unwrap());
+}
+
+#[test]
+fn reports_leaked_chain() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    let start_block = fs.allocate_blocks(3).unwrap();
+
+    assert_eq!(
+        fs.check().unwrap().problems,
+        [Problem::LeakedChain {
+            start_block,
+            blocks: 3
+        }]
+    );
+}
+
+#[test]
+fn reports_chain_loop() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[1; 1500], 0)
+        .unwrap();
+
+    let chain = fs.get_chain(file.start_block).unwrap();
+    fs.write_block(chain[2], chain[0]).unwrap();
+
+    let problems = fs.check().unwrap().problems;
+
+    assert!(
+        problems
+            .iter()
+            .any(|p| matches!(p, Problem::BrokenChain { path, .. } if path == "/file")),
+        "{problems:?}"
+    );
+}
+
+#[test]
+fn reports_cross_linked_chains() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let first = fs.create_file(root, "first").unwrap();
+    let second = fs.create_file(root, "second").unwrap();
+    fs.write_contents_by_entity(root, &second, &[2; 1000], 0)
+        .unwrap();
+
+    // `first` runs into the chain of `second`.
+    fs.write_block(first.start_block, second.start_block)
+        .unwrap();
+
+    let problems = fs.check().unwrap().problems;
+
+    assert!(
+        problems.contains(&Problem::CrossLinked {
+            block: second.start_block,
+            first: "/first".to_string(),
+            second: "/second".to_string(),
+        }) || problems.contains(&Problem::CrossLinked {
+            block: second.start_block,
+            first: "/second".to_string(),
+            second: "/first".to_string(),
+        }),
+        "{problems:?}"
+    );
+}
+
+#[test]
+fn reports_size_mismatch() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[1; 100], 0)
+        .unwrap();
+    fs.extend_chain_by(file.start_block, 2).unwrap();
+
+    assert_eq!(
+        fs.check().unwrap().problems,
+        [Problem::SizeMismatch {
+            path: "/file".to_string(),
+            size: 100,
+            blocks: 3
+        }]
+    );
+}
+
+#[test]
+fn reports_bad_dot_entry() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let other = fs.create_directory(root, "other").unwrap();
+
+    let this = fs.find_entity(dir.start_block, ".").unwrap();
+    let mut wrong = this.clone();
+    wrong.start_block = other.start_block;
+    fs.overwrite_entity_header(dir.start_block, &this, &wrong)
+        .unwrap();
+
+    assert_eq!(
+        fs.check().unwrap().problems,
+        [Problem::BadDotEntry {
+            directory: "/dir".to_string(),
+            name: ".",
+            expected: dir.start_block,
+            found: Some(other.start_block),
+        }]
+    );
+}
+
+#[test]
+fn reports_unparseable_record() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let file = fs.create_file(dir.start_block, "file").unwrap();
+    let offset = fs.get_entity_offset(dir.start_block, &file).unwrap();
+
+    // A header size running past the end of the directory.
+    fs.write_blocks_data(dir.start_block, &[0xFF; 4], offset as u64)
+        .unwrap();
+
+    let problems = fs.check().unwrap().problems;
+
+    assert!(
+        problems.contains(&Problem::BadRecord {
+            directory: "/dir".to_string(),
+            offset,
+        }),
+        "{problems:?}"
+    );
+}
+
+#[test]
+fn check_on_read_only_mount_writes_nothing() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        fs.allocate_blocks(2).unwrap();
+        fs.unmount().unwrap();
+    }
+
+    let before = device.data.clone();
+
+    {
+        let read_only = MountOptions {
+            read_only: true,
+            ..MountOptions::default()
+        };
+        let mut fs = NoctFS::mount(&mut device, read_only).unwrap();
+
+        assert!(!fs.check().unwrap().is_clean());
+    }
+
+    assert!(device.data == before, "check wrote to the device");
+}
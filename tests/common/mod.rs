@@ -0,0 +1,122 @@
+//! Helpers shared by the integration tests.
+
+// Every test crate uses a different subset of these.
+#![allow(dead_code)]
+
+use no_std_io::io::{self, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use noctfs::{device::Device, entity::Entity, FormatOptions, NoctFS, NoctFSError};
+
+/// In-memory device that drops every write once `writes_left` runs out, as if power was cut.
+pub struct MemoryDevice {
+    pub data: Vec<u8>,
+    pub position: usize,
+    pub writes_left: Option<usize>,
+}
+
+impl MemoryDevice {
+    pub fn new(data: Vec<u8>, writes_left: Option<usize>) -> Self {
+        Self {
+            data,
+            position: 0,
+            writes_left,
+        }
+    }
+}
+
+impl Read for MemoryDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = buf.len().min(self.data.len() - self.position);
+
+        buf[..count].copy_from_slice(&self.data[self.position..self.position + count]);
+        self.position += count;
+
+        Ok(count)
+    }
+}
+
+impl Write for MemoryDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(writes_left) = &mut self.writes_left {
+            if *writes_left == 0 {
+                return Err(Error::new(ErrorKind::Other, "power cut"));
+            }
+
+            *writes_left -= 1;
+        }
+
+        self.data[self.position..self.position + buf.len()].copy_from_slice(buf);
+        self.position += buf.len();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryDevice {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset as usize,
+            SeekFrom::End(offset) => (self.data.len() as i64 + offset) as usize,
+            SeekFrom::Current(offset) => (self.position as i64 + offset) as usize,
+        };
+
+        Ok(self.position as u64)
+    }
+}
+
+impl Device for MemoryDevice {}
+
+/// Size of the small volumes most tests use, which are quick to fill up.
+pub const SMALL_VOLUME_SIZE: usize = 256 * 1024;
+
+/// Formats a small volume with 512-byte blocks and the given journal size, `Some(0)` for none.
+pub fn small_volume(journal_size: Option<usize>) -> MemoryDevice {
+    let mut device = MemoryDevice::new(vec![0; SMALL_VOLUME_SIZE], None);
+
+    NoctFS::format_with(
+        &mut device,
+        &FormatOptions {
+            block_size: Some(512),
+            journal_size,
+            ..FormatOptions::default()
+        },
+    )
+    .unwrap();
+
+    device
+}
+
+/// Start block of the root directory.
+pub fn root(fs: &mut NoctFS) -> u64 {
+    fs.get_root_entity().unwrap().start_block
+}
+
+/// Writes to a new file in the root until no block is left, returning the file.
+pub fn fill(fs: &mut NoctFS) -> Entity {
+    let root = root(fs);
+    let filler = fs.create_file(root, "filler").unwrap();
+    let block = vec![0xAA; fs.block_size()];
+    let mut offset = 0;
+
+    loop {
+        match fs.write_contents_by_entity(root, &filler, &block, offset) {
+            Ok(written) => offset += written as u64,
+            Err(NoctFSError::NoSpace) => break,
+            Err(e) => panic!("filling the volume: {e}"),
+        }
+    }
+
+    assert_eq!(fs.stats().unwrap().free_blocks, 0);
+
+    fs.find_entity(root, "filler").unwrap()
+}
+
+/// Panics with the checker's report unless the volume is consistent.
+pub fn assert_clean(fs: &mut NoctFS) {
+    let report = fs.check().unwrap();
+
+    assert!(report.is_clean(), "{report}");
+}
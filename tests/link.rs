@@ -0,0 +1,166 @@
+//! Hard links and the link table.
+
+mod common;
+
+use common::{assert_clean, fill, root, small_volume};
+use noctfs::{NoctFS, NoctFSError};
+
+#[test]
+fn links_share_contents() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let file = fs.create_file(root, "file").unwrap();
+    let link = fs.link(root, &file, dir.start_block, "link").unwrap();
+
+    assert_eq!(link.start_block, file.start_block);
+    assert_eq!(fs.link_count(&file), 2);
+
+    fs.write_contents_by_entity(dir.start_block, &link, b"shared", 0)
+        .unwrap();
+
+    let file = fs.find_entity(root, "file").unwrap();
+    let mut data = [0; 6];
+
+    assert_eq!(file.size, 6);
+
+    fs.read_contents_by_entity(root, &file, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"shared");
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn chain_outlives_all_but_last_link() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let free_blocks = fs.stats().unwrap().free_blocks;
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[4; 1500], 0)
+        .unwrap();
+    fs.link(root, &file, root, "first").unwrap();
+    fs.link(root, &file, root, "second").unwrap();
+
+    assert_eq!(fs.link_count(&file), 3);
+
+    fs.delete_file(root, &file).unwrap();
+
+    let first = fs.find_entity(root, "first").unwrap();
+
+    assert_eq!(fs.link_count(&first), 2);
+    assert_eq!(fs.get_chain(first.start_block).unwrap().len(), 3);
+    assert_clean(&mut fs);
+
+    fs.delete_file(root, &first).unwrap();
+
+    let second = fs.find_entity(root, "second").unwrap();
+
+    assert_eq!(fs.link_count(&second), 1);
+
+    fs.delete_file(root, &second).unwrap();
+    fs.sync().unwrap();
+
+    assert_eq!(fs.stats().unwrap().free_blocks, free_blocks);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn link_table_survives_remount() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        let dir = fs.create_directory(root, "dir").unwrap();
+        let file = fs.create_file(root, "file").unwrap();
+        fs.link(root, &file, dir.start_block, "link").unwrap();
+        fs.unmount().unwrap();
+    }
+
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let file = fs.lookup("/file").unwrap();
+
+    assert_eq!(fs.link_count(&file), 2);
+    assert_clean(&mut fs);
+
+    // Deleting one record mustn't free the chain the other one still uses.
+    let root = root(&mut fs);
+    fs.delete_file(root, &file).unwrap();
+
+    let link = fs.lookup("/dir/link").unwrap();
+
+    assert_eq!(fs.link_count(&link), 1);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn metadata_changes_reach_every_link() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.link(root, &file, root, "link").unwrap();
+    fs.chmod(root, &file, 0o600).unwrap();
+
+    assert_eq!(fs.find_entity(root, "link").unwrap().mode, 0o600);
+}
+
+#[test]
+fn rejects_directories_and_taken_names() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let file = fs.create_file(root, "file").unwrap();
+
+    assert!(matches!(
+        fs.link(root, &dir, root, "link"),
+        Err(NoctFSError::IsADirectory)
+    ));
+    assert!(matches!(
+        fs.link(root, &file, root, "dir"),
+        Err(NoctFSError::AlreadyExists)
+    ));
+    assert_eq!(fs.link_count(&file), 1);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn link_on_full_volume_changes_nothing() {
+    for journal_size in [None, Some(0)] {
+        let mut device = small_volume(journal_size);
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        let file = fs.create_file(root, "file").unwrap();
+
+        fill(&mut fs);
+
+        // The link table needs a block of its own.
+        assert!(matches!(
+            fs.link(root, &file, root, "link"),
+            Err(NoctFSError::NoSpace)
+        ));
+        assert_eq!(fs.link_count(&file), 1);
+        assert!(matches!(
+            fs.find_entity(root, "link"),
+            Err(NoctFSError::NotFound)
+        ));
+        assert_clean(&mut fs);
+
+        fs.unmount().unwrap();
+
+        let mut fs = NoctFS::new(&mut device).unwrap();
+
+        assert_clean(&mut fs);
+    }
+}
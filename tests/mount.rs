@@ -0,0 +1,67 @@
+//! Mounting, unmounting and the volume state.
+
+mod common;
+
+use common::{root, small_volume};
+use noctfs::{bootsector::VolumeState, NoctFS, NoctFSError};
+
+#[test]
+fn unmount_marks_volume_clean() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        fs.create_file(root, "file").unwrap();
+
+        assert_eq!(fs.info().state, VolumeState::Dirty);
+
+        fs.unmount().unwrap();
+    }
+
+    let fs = NoctFS::new(&mut device).unwrap();
+
+    assert_eq!(fs.info().state, VolumeState::Clean);
+}
+
+#[test]
+fn failed_mount_leaves_volume_alone() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+        let total_blocks = fs.stats().unwrap().total_blocks;
+
+        let file = fs.create_file(root, "file").unwrap();
+        fs.sync().unwrap();
+
+        let unused: Vec<u64> = (1..total_blocks)
+            .filter(|&block| fs.get_block(block).unwrap() == Some(0))
+            .collect();
+
+        // The only block the link takes is the link table's.
+        fs.link(root, &file, root, "link").unwrap();
+        fs.sync().unwrap();
+
+        let table_block = unused
+            .into_iter()
+            .find(|&block| fs.get_block(block).unwrap() != Some(0))
+            .unwrap();
+
+        // A loop in the table's chain fails the mount after the journal was replayed.
+        fs.write_block(table_block, table_block).unwrap();
+        fs.sync().unwrap();
+
+        std::mem::forget(fs);
+    }
+
+    let before = device.data.clone();
+
+    assert!(matches!(
+        NoctFS::new(&mut device),
+        Err(NoctFSError::Corrupted { .. })
+    ));
+    assert!(device.data == before, "failed mount wrote to the device");
+}
@@ -0,0 +1,111 @@
+//! Permission checks with credentials set.
+
+mod common;
+
+use common::{root, small_volume};
+use noctfs::{access::Credentials, NoctFS, NoctFSError};
+
+#[test]
+fn owner_reads_others_dont() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "secret").unwrap();
+    fs.write_contents_by_entity(root, &file, b"secret", 0)
+        .unwrap();
+    fs.chown(root, &file, Some(1000), Some(1000)).unwrap();
+    let file = fs.chmod(root, &file, 0o600).unwrap();
+    let mut data = [0; 6];
+
+    fs.set_credentials(Some(Credentials::new(1000, 1000)));
+    fs.read_contents_by_entity(root, &file, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"secret");
+
+    fs.set_credentials(Some(Credentials::new(1001, 1001)));
+
+    assert!(matches!(
+        fs.read_contents_by_entity(root, &file, &mut data, 0),
+        Err(NoctFSError::PermissionDenied)
+    ));
+    assert!(matches!(
+        fs.open("/secret"),
+        Err(NoctFSError::PermissionDenied)
+    ));
+}
+
+#[test]
+fn forged_entity_doesnt_grant_access() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "secret").unwrap();
+    let file = fs.chmod(root, &file, 0o600).unwrap();
+
+    let mut forged = file.clone();
+    forged.uid = 1000;
+    forged.mode = 0o644;
+
+    fs.set_credentials(Some(Credentials::new(1000, 1000)));
+
+    assert!(matches!(
+        fs.read_contents_by_entity(root, &forged, &mut [0; 4], 0),
+        Err(NoctFSError::PermissionDenied)
+    ));
+    assert!(matches!(
+        fs.open_entity(root, forged),
+        Err(NoctFSError::PermissionDenied)
+    ));
+}
+
+#[test]
+fn access_follows_mode_changes() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.chmod(root, &file, 0o644).unwrap();
+
+    fs.set_credentials(Some(Credentials::new(1000, 1000)));
+
+    assert!(fs.open_entity(root, file.clone()).is_ok());
+
+    // An entity handed out before the change is not good for reading afterwards.
+    fs.set_credentials(None);
+    fs.chmod(root, &file, 0o600).unwrap();
+    fs.set_credentials(Some(Credentials::new(1000, 1000)));
+
+    assert!(matches!(
+        fs.read_contents_by_entity(root, &file, &mut [0; 4], 0),
+        Err(NoctFSError::PermissionDenied)
+    ));
+    assert!(matches!(
+        fs.open_entity(root, file),
+        Err(NoctFSError::PermissionDenied)
+    ));
+}
+
+#[test]
+fn creating_needs_write_permission_on_directory() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    fs.chmod(root, &dir, 0o755).unwrap();
+
+    fs.set_credentials(Some(Credentials::new(1000, 1000)));
+
+    assert!(matches!(
+        fs.create_file(dir.start_block, "file"),
+        Err(NoctFSError::PermissionDenied)
+    ));
+    assert!(matches!(
+        fs.chmod(root, &dir, 0o777),
+        Err(NoctFSError::PermissionDenied)
+    ));
+}
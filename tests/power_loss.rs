@@ -0,0 +1,132 @@
+//! Cuts power at every write of a workload and checks what the next mount makes of the volume.
+
+mod common;
+
+use common::MemoryDevice;
+use noctfs::{FormatOptions, MountOptions, NoctFS, NoctFSError, Result};
+
+const VOLUME_SIZE: usize = 4 << 20;
+
+/// Formats a volume holding a file for the workload to delete.
+fn prepare(journal_size: Option<usize>) -> Vec<u8> {
+    let mut device = MemoryDevice::new(vec![0; VOLUME_SIZE], None);
+
+    NoctFS::format_with(
+        &mut device,
+        &FormatOptions {
+            journal_size,
+            ..FormatOptions::default()
+        },
+    )
+    .unwrap();
+
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = fs.get_root_entity().unwrap().start_block;
+    let old = fs.create_file(root, "old").unwrap();
+
+    fs.write_contents_by_entity(root, &old, &[1; 20000], 0)
+        .unwrap();
+    fs.unmount().unwrap();
+
+    device.data
+}
+
+fn workload(fs: &mut NoctFS) -> Result<()> {
+    let root = fs.get_root_entity()?.start_block;
+
+    let dir = fs.create_directory(root, "dir")?;
+    let file = fs.create_file(dir.start_block, "file")?;
+    fs.write_contents_by_entity(dir.start_block, &file, &[7; 30000], 0)?;
+
+    let other = fs.create_file(root, "other")?;
+    fs.link(root, &other, dir.start_block, "link")?;
+
+    let file = fs.find_entity(dir.start_block, "file")?;
+    fs.rename(dir.start_block, &file, root, "moved")?;
+    fs.create_symlink(root, "symlink", "dir/link")?;
+    fs.chmod(root, &dir, 0o700)?;
+
+    let old = fs.find_entity(root, "old")?;
+    fs.delete_entity(root, &old)?;
+
+    fs.sync()
+}
+
+/// Number of device writes the workload makes when nothing goes wrong.
+fn count_writes(image: &[u8]) -> usize {
+    let mut device = MemoryDevice::new(image.to_vec(), Some(usize::MAX));
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    workload(&mut fs).unwrap();
+    fs.unmount().unwrap();
+
+    usize::MAX - device.writes_left.unwrap()
+}
+
+/// Runs the workload with power cut after `writes` writes, returning what's left on the device.
+fn crash_after(image: &[u8], writes: usize) -> Vec<u8> {
+    let mut device = MemoryDevice::new(image.to_vec(), Some(writes));
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    let _ = workload(&mut fs);
+
+    // Nothing gets to run on the way out.
+    std::mem::forget(fs);
+
+    device.data
+}
+
+/// Number of cut points the volume came back from without the checker.
+fn power_loss(journal_size: Option<usize>) -> usize {
+    let image = prepare(journal_size);
+    let total = count_writes(&image);
+    let mut consistent = 0;
+
+    for writes in 0..total {
+        let crashed = crash_after(&image, writes);
+        let mut device = MemoryDevice::new(crashed.clone(), None);
+
+        // A read-only mount sees a transaction left in the journal, but doesn't replay it.
+        let read_only = MountOptions {
+            read_only: true,
+            ..MountOptions::default()
+        };
+
+        NoctFS::mount(&mut device, read_only)
+            .and_then(|mut fs| fs.check())
+            .unwrap_or_else(|e| panic!("cut after {writes} writes: {e}"));
+
+        assert!(
+            device.data == crashed,
+            "read-only mount wrote to the device"
+        );
+
+        match NoctFS::new(&mut device) {
+            Ok(mut fs) => {
+                let report = fs.check().unwrap();
+
+                assert!(report.is_clean(), "cut after {writes} writes: {report}");
+
+                consistent += 1;
+            }
+            Err(NoctFSError::DirtyVolume) => {}
+            Err(e) => panic!("cut after {writes} writes: {e}"),
+        };
+    }
+
+    consistent
+}
+
+#[test]
+fn journal_replay_leaves_volume_consistent() {
+    let image = prepare(None);
+    let total = count_writes(&image);
+
+    assert_eq!(power_loss(None), total);
+}
+
+#[test]
+fn volume_without_journal_reports_dirty() {
+    // Only a cut before the first write leaves the volume clean.
+    assert_eq!(power_loss(Some(0)), 1);
+}
@@ -0,0 +1,213 @@
+//! Renaming and moving entities.
+
+mod common;
+
+use common::{assert_clean, fill, root, small_volume};
+use noctfs::{NoctFS, NoctFSError};
+
+/// A name long enough for a few records to fill a directory block.
+fn long_name(nr: usize) -> String {
+    format!("{nr:0>200}")
+}
+
+#[test]
+fn renames_within_directory() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "old").unwrap();
+    fs.write_contents_by_entity(root, &file, b"contents", 0)
+        .unwrap();
+
+    let renamed = fs.rename(root, &file, root, "new").unwrap();
+    let mut data = [0; 8];
+
+    assert_eq!(renamed.name, "new");
+    assert_eq!(renamed.start_block, file.start_block);
+    assert!(matches!(
+        fs.find_entity(root, "old"),
+        Err(NoctFSError::NotFound)
+    ));
+
+    let found = fs.find_entity(root, "new").unwrap();
+    fs.read_contents_by_entity(root, &found, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"contents");
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn moves_directory_to_another_parent() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let parent = fs.create_directory(root, "parent").unwrap();
+    let moved = fs.create_directory(root, "moved").unwrap();
+    fs.create_file(moved.start_block, "inside").unwrap();
+
+    fs.rename(root, &moved, parent.start_block, "moved")
+        .unwrap();
+
+    assert_eq!(
+        fs.find_entity(moved.start_block, "..").unwrap().start_block,
+        parent.start_block
+    );
+    assert!(fs.lookup("/parent/moved/inside").is_ok());
+    assert!(matches!(fs.lookup("/moved"), Err(NoctFSError::NotFound)));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn replaces_existing_file() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let source = fs.create_file(root, "source").unwrap();
+    let free_blocks = fs.stats().unwrap().free_blocks;
+
+    let target = fs.create_file(root, "target").unwrap();
+    fs.write_contents_by_entity(root, &target, &[1; 2000], 0)
+        .unwrap();
+
+    fs.rename(root, &source, root, "target").unwrap();
+
+    assert_eq!(
+        fs.find_entity(root, "target").unwrap().start_block,
+        source.start_block
+    );
+    assert!(matches!(
+        fs.find_entity(root, "source"),
+        Err(NoctFSError::NotFound)
+    ));
+    assert_eq!(fs.stats().unwrap().free_blocks, free_blocks);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn rejects_moving_directory_into_itself() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let outer = fs.create_directory(root, "outer").unwrap();
+    let inner = fs.create_directory(outer.start_block, "inner").unwrap();
+
+    assert!(matches!(
+        fs.rename(root, &outer, inner.start_block, "outer"),
+        Err(NoctFSError::InvalidMove)
+    ));
+    assert!(matches!(
+        fs.rename(root, &outer, outer.start_block, "outer"),
+        Err(NoctFSError::InvalidMove)
+    ));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn rejects_incompatible_targets() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let full = fs.create_directory(root, "full").unwrap();
+    fs.create_file(full.start_block, "inside").unwrap();
+
+    assert!(matches!(
+        fs.rename(root, &file, root, "dir"),
+        Err(NoctFSError::IsADirectory)
+    ));
+    assert!(matches!(
+        fs.rename(root, &dir, root, "file"),
+        Err(NoctFSError::NotADirectory)
+    ));
+    assert!(matches!(
+        fs.rename(root, &dir, root, "full"),
+        Err(NoctFSError::DirectoryNotEmpty)
+    ));
+    assert_eq!(fs.list_directory(root).unwrap().len(), 5);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn move_on_full_volume_keeps_entity() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dst = fs.create_directory(root, "dst").unwrap();
+
+    for nr in 0..8 {
+        fs.create_file(root, format!("file-{nr}")).unwrap();
+    }
+
+    fill(&mut fs);
+
+    for nr in 0..8 {
+        let file = fs.find_entity(root, &format!("file-{nr}")).unwrap();
+
+        match fs.rename(root, &file, dst.start_block, long_name(nr)) {
+            Ok(_) => continue,
+            Err(NoctFSError::NoSpace) => {}
+            Err(e) => panic!("{e}"),
+        }
+
+        assert!(fs.find_entity(root, &file.name).is_ok());
+        assert!(matches!(
+            fs.find_entity(dst.start_block, &long_name(nr)),
+            Err(NoctFSError::NotFound)
+        ));
+        assert_clean(&mut fs);
+
+        return;
+    }
+
+    panic!("the destination never ran out of space");
+}
+
+#[test]
+fn rename_on_full_volume_keeps_entity() {
+    for journal_size in [None, Some(0)] {
+        let mut device = small_volume(journal_size);
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        for nr in 0..8 {
+            fs.create_file(root, format!("file-{nr}")).unwrap();
+        }
+
+        fill(&mut fs);
+
+        let mut ran_out = false;
+
+        // Longer names need more room, until the directory has to grow.
+        for nr in 0..8 {
+            let file = fs.find_entity(root, &format!("file-{nr}")).unwrap();
+
+            match fs.rename(root, &file, root, long_name(nr)) {
+                Ok(_) => continue,
+                Err(NoctFSError::NoSpace) => {}
+                Err(e) => panic!("{e}"),
+            }
+
+            assert!(fs.find_entity(root, &file.name).is_ok());
+            assert_clean(&mut fs);
+
+            ran_out = true;
+            break;
+        }
+
+        assert!(ran_out, "the directory never ran out of space");
+
+        fs.unmount().unwrap();
+
+        let mut fs = NoctFS::new(&mut device).unwrap();
+
+        assert_clean(&mut fs);
+    }
+}
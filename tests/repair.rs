@@ -0,0 +1,302 @@
+//! Repair mode of the checker.
+
+mod common;
+
+use common::{assert_clean, root, small_volume};
+use noctfs::{
+    check::{Repair, RepairOptions},
+    MountOptions, NoctFS, NoctFSError,
+};
+
+#[test]
+fn recovers_leaked_chain_into_lost_and_found() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    let start_block = fs.allocate_blocks(2).unwrap();
+    fs.write_blocks_data(start_block, b"recovered", 0).unwrap();
+
+    let report = fs.repair(RepairOptions::default()).unwrap();
+
+    assert_eq!(
+        report.repairs,
+        [Repair::RecoverChain {
+            start_block,
+            blocks: 2
+        }]
+    );
+    assert_clean(&mut fs);
+
+    let lost_found = fs.lookup("/lost+found").unwrap().start_block;
+    let recovered = fs
+        .lookup(&format!("/lost+found/chain-{start_block}"))
+        .unwrap();
+    let mut data = [0; 9];
+
+    assert_eq!(recovered.size, 2 * fs.block_size() as u64);
+
+    fs.read_contents_by_entity(lost_found, &recovered, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"recovered");
+}
+
+#[test]
+fn discards_leaked_chain() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let free_blocks = fs.stats().unwrap().free_blocks;
+
+    fs.allocate_blocks(3).unwrap();
+    fs.repair(RepairOptions {
+        discard_lost_chains: true,
+        ..RepairOptions::default()
+    })
+    .unwrap();
+
+    assert_clean(&mut fs);
+    assert_eq!(fs.stats().unwrap().free_blocks, free_blocks);
+    assert!(matches!(
+        fs.lookup("/lost+found"),
+        Err(NoctFSError::NotFound)
+    ));
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        fs.allocate_blocks(2).unwrap();
+        fs.unmount().unwrap();
+    }
+
+    let before = device.data.clone();
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let report = fs
+            .repair(RepairOptions {
+                dry_run: true,
+                ..RepairOptions::default()
+            })
+            .unwrap();
+
+        assert!(report.dry_run);
+        assert_eq!(report.repairs.len(), 1);
+        assert!(!fs.check().unwrap().is_clean());
+    }
+
+    assert!(device.data == before, "dry run wrote to the device");
+}
+
+#[test]
+fn truncates_chain_longer_than_file() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[1; 100], 0)
+        .unwrap();
+
+    let free_blocks = fs.stats().unwrap().free_blocks;
+
+    fs.extend_chain_by(file.start_block, 2).unwrap();
+    fs.repair(RepairOptions::default()).unwrap();
+
+    assert_clean(&mut fs);
+    assert_eq!(fs.get_chain(file.start_block).unwrap().len(), 1);
+    assert_eq!(fs.stats().unwrap().free_blocks, free_blocks);
+}
+
+#[test]
+fn rewrites_bad_dot_entry() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+
+    let parent = fs.find_entity(dir.start_block, "..").unwrap();
+    let mut wrong = parent.clone();
+    wrong.start_block = dir.start_block;
+    fs.overwrite_entity_header(dir.start_block, &parent, &wrong)
+        .unwrap();
+
+    fs.repair(RepairOptions::default()).unwrap();
+
+    assert_clean(&mut fs);
+    assert_eq!(
+        fs.find_entity(dir.start_block, "..").unwrap().start_block,
+        root
+    );
+}
+
+#[test]
+fn drops_unparseable_records_and_recovers_what_they_held() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+    let file = fs.create_file(dir.start_block, "file").unwrap();
+    fs.write_contents_by_entity(dir.start_block, &file, b"behind the damage", 0)
+        .unwrap();
+
+    let offset = fs.get_entity_offset(dir.start_block, &file).unwrap();
+    fs.write_blocks_data(dir.start_block, &[0xFF; 4], offset as u64)
+        .unwrap();
+
+    let report = fs.repair(RepairOptions::default()).unwrap();
+
+    assert!(
+        report
+            .repairs
+            .iter()
+            .any(|repair| matches!(repair, Repair::DropRecords { offset: o, .. } if *o == offset)),
+        "{report}"
+    );
+
+    // The file's chain only shows up as leaked once its record is gone.
+    fs.repair(RepairOptions::default()).unwrap();
+
+    assert_clean(&mut fs);
+    assert!(fs.list_directory(dir.start_block).unwrap().len() == 2);
+
+    let lost_found = fs.lookup("/lost+found").unwrap().start_block;
+    let recovered = fs
+        .lookup(&format!("/lost+found/chain-{}", file.start_block))
+        .unwrap();
+    let mut data = [0; 17];
+
+    fs.read_contents_by_entity(lost_found, &recovered, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"behind the damage");
+}
+
+#[test]
+fn ends_chain_at_break() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[5; 1500], 0)
+        .unwrap();
+
+    let chain = fs.get_chain(file.start_block).unwrap();
+    fs.write_block(chain[1], 0).unwrap();
+
+    let report = fs.repair(RepairOptions::default()).unwrap();
+
+    assert!(
+        report.repairs.contains(&Repair::EndChain {
+            path: "/file".to_string(),
+            block: chain[0],
+        }),
+        "{report}"
+    );
+    assert_clean(&mut fs);
+
+    let file = fs.find_entity(root, "file").unwrap();
+
+    assert_eq!(file.size, fs.block_size() as u64);
+    assert_eq!(*fs.get_chain(file.start_block).unwrap(), [chain[0]]);
+}
+
+#[test]
+fn keeps_leaks_of_broken_directory_for_next_run() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+
+    for nr in 0..12 {
+        let file = fs
+            .create_file(dir.start_block, format!("file-{nr}"))
+            .unwrap();
+        fs.write_contents_by_entity(dir.start_block, &file, &[nr; 10], 0)
+            .unwrap();
+    }
+
+    let chain = fs.get_chain(dir.start_block).unwrap();
+    assert!(chain.len() > 1);
+
+    fs.write_block(chain[1], 0).unwrap();
+
+    // Files past the break are only found once the directory is readable again,
+    // and must not be recovered while they might still be listed in it.
+    let report = fs.repair(RepairOptions::default()).unwrap();
+
+    assert!(
+        !report
+            .repairs
+            .iter()
+            .any(|repair| matches!(repair, Repair::RecoverChain { .. })),
+        "{report}"
+    );
+
+    for _ in 0..2 {
+        fs.repair(RepairOptions::default()).unwrap();
+    }
+
+    assert_clean(&mut fs);
+    assert!(fs.find_entity(dir.start_block, "file-0").is_ok());
+}
+
+#[test]
+fn repairs_volume_left_by_crash() {
+    let mut device = small_volume(Some(0));
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        let kept = fs.create_file(root, "kept").unwrap();
+        fs.write_contents_by_entity(root, &kept, b"kept", 0)
+            .unwrap();
+        fs.sync().unwrap();
+
+        // The record reaches the device, the block map entries of its chain don't.
+        let lost = fs.create_file(root, "lost").unwrap();
+        fs.write_contents_by_entity(root, &lost, b"lost", 0)
+            .unwrap();
+
+        std::mem::forget(fs);
+    }
+
+    assert!(matches!(
+        NoctFS::new(&mut device),
+        Err(NoctFSError::DirtyVolume)
+    ));
+
+    {
+        let allow_dirty = MountOptions {
+            allow_dirty: true,
+            ..MountOptions::default()
+        };
+        let mut fs = NoctFS::mount(&mut device, allow_dirty).unwrap();
+
+        assert!(!fs.check().unwrap().is_clean());
+
+        fs.repair(RepairOptions::default()).unwrap();
+        fs.unmount().unwrap();
+    }
+
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+    let mut data = [0; 4];
+
+    assert_clean(&mut fs);
+
+    let kept = fs.lookup("/kept").unwrap();
+    fs.read_contents_by_entity(root, &kept, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"kept");
+    assert!(matches!(fs.lookup("/lost"), Err(NoctFSError::NotFound)));
+}
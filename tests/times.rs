@@ -0,0 +1,113 @@
+//! Timestamps of entities and of the directories holding them.
+
+mod common;
+
+use std::{cell::Cell, rc::Rc};
+
+use common::{root, small_volume};
+use noctfs::{entity::Timestamps, NoctFS};
+
+/// Sets a clock the test moves by hand.
+fn manual_clock(fs: &mut NoctFS) -> Rc<Cell<u64>> {
+    let time = Rc::new(Cell::new(100));
+    let clock = time.clone();
+
+    fs.set_clock(move || clock.get());
+
+    time
+}
+
+/// Modification and change time of the directory at `path`.
+fn directory_times(fs: &mut NoctFS, path: &str) -> (u64, u64) {
+    let Timestamps {
+        modified, changed, ..
+    } = fs.lookup(path).unwrap().times;
+
+    (modified, changed)
+}
+
+#[test]
+fn new_entity_gets_current_time() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let time = manual_clock(&mut fs);
+    let root = root(&mut fs);
+
+    time.set(1234);
+
+    let file = fs.create_file(root, "file").unwrap();
+
+    assert_eq!(file.times, Timestamps::at(1234));
+    assert_eq!(fs.find_entity(root, "file").unwrap().times, file.times);
+}
+
+#[test]
+fn writing_updates_modification_time() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let time = manual_clock(&mut fs);
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+
+    time.set(200);
+    fs.write_contents_by_entity(root, &file, b"data", 0)
+        .unwrap();
+
+    let times = fs.find_entity(root, "file").unwrap().times;
+
+    assert_eq!(
+        times,
+        Timestamps {
+            created: 100,
+            modified: 200,
+            accessed: 100,
+            changed: 200,
+        }
+    );
+}
+
+#[test]
+fn directory_changes_with_its_entries() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let time = manual_clock(&mut fs);
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+
+    assert_eq!(directory_times(&mut fs, "/"), (100, 100));
+
+    time.set(200);
+    let file = fs.create_file(dir.start_block, "file").unwrap();
+
+    assert_eq!(directory_times(&mut fs, "/dir"), (200, 200));
+    // Only the record of `dir` changed in the root, not its entries.
+    assert_eq!(directory_times(&mut fs, "/"), (100, 100));
+
+    time.set(300);
+    fs.rename(dir.start_block, &file, root, "moved").unwrap();
+
+    assert_eq!(directory_times(&mut fs, "/dir"), (300, 300));
+    assert_eq!(directory_times(&mut fs, "/"), (300, 300));
+
+    time.set(400);
+    fs.create_symlink(dir.start_block, "symlink", "../moved")
+        .unwrap();
+
+    assert_eq!(directory_times(&mut fs, "/dir"), (400, 400));
+
+    time.set(500);
+    let moved = fs.find_entity(root, "moved").unwrap();
+    fs.link(root, &moved, dir.start_block, "link").unwrap();
+
+    assert_eq!(directory_times(&mut fs, "/dir"), (500, 500));
+
+    time.set(600);
+    let link = fs.find_entity(dir.start_block, "link").unwrap();
+    fs.delete_file(dir.start_block, &link).unwrap();
+
+    assert_eq!(directory_times(&mut fs, "/dir"), (600, 600));
+    assert_eq!(fs.lookup("/dir").unwrap().times.created, 100);
+    assert_eq!(directory_times(&mut fs, "/"), (300, 300));
+}
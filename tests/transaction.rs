@@ -0,0 +1,160 @@
+//! Transactions started by `NoctFS::transaction`.
+
+mod common;
+
+use common::{assert_clean, root, small_volume};
+use noctfs::{NoctFS, NoctFSError};
+
+#[test]
+fn commits_every_operation() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        fs.transaction(|fs| {
+            let dir = fs.create_directory(root, "dir")?;
+            let file = fs.create_file(dir.start_block, "file")?;
+            fs.write_contents_by_entity(dir.start_block, &file, b"contents", 0)?;
+            fs.rename(dir.start_block, &file, root, "moved")
+        })
+        .unwrap();
+
+        fs.unmount().unwrap();
+    }
+
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    assert!(fs.lookup("/dir").is_ok());
+    assert_eq!(fs.lookup("/moved").unwrap().size, 8);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn failure_undoes_everything() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let kept = fs.create_file(root, "kept").unwrap();
+    let free_blocks = fs.stats().unwrap().free_blocks;
+
+    let result: Result<(), _> = fs.transaction(|fs| {
+        let file = fs.create_file(root, "file")?;
+        fs.write_contents_by_entity(root, &file, &[1; 2000], 0)?;
+        fs.delete_file(root, &kept)?;
+
+        Err(NoctFSError::InvalidName)
+    });
+
+    assert!(matches!(result, Err(NoctFSError::InvalidName)));
+    assert!(fs.find_entity(root, "kept").is_ok());
+    assert!(matches!(
+        fs.find_entity(root, "file"),
+        Err(NoctFSError::NotFound)
+    ));
+    assert_eq!(fs.stats().unwrap().free_blocks, free_blocks);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn failed_nested_transaction_undoes_only_its_own_changes() {
+    let mut device = small_volume(None);
+
+    {
+        let mut fs = NoctFS::new(&mut device).unwrap();
+        let root = root(&mut fs);
+
+        fs.transaction(|fs| {
+            fs.create_file(root, "outer")?;
+
+            let inner = fs.transaction(|fs| {
+                let file = fs.create_file(root, "inner")?;
+                fs.write_contents_by_entity(root, &file, &[2; 1500], 0)?;
+
+                Err::<(), _>(NoctFSError::InvalidName)
+            });
+
+            assert!(inner.is_err());
+
+            fs.create_file(root, "after")
+        })
+        .unwrap();
+
+        fs.unmount().unwrap();
+    }
+
+    let mut fs = NoctFS::new(&mut device).unwrap();
+
+    assert!(fs.lookup("/outer").is_ok());
+    assert!(fs.lookup("/after").is_ok());
+    assert!(matches!(fs.lookup("/inner"), Err(NoctFSError::NotFound)));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn failed_operation_inside_transaction_leaves_nothing_behind() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+
+    fs.transaction(|fs| {
+        fs.write_contents_by_entity(root, &file, b"kept", 0)?;
+
+        // Fails after the first blocks are written, since the journal can't hold it all.
+        let large = vec![3; 64 * 1024];
+        assert!(fs.write_contents_by_entity(root, &file, &large, 0).is_err());
+
+        Ok(())
+    })
+    .unwrap();
+
+    let file = fs.find_entity(root, "file").unwrap();
+    let mut data = [0; 4];
+
+    assert_eq!(file.size, 4);
+
+    fs.read_contents_by_entity(root, &file, &mut data, 0)
+        .unwrap();
+
+    assert_eq!(&data, b"kept");
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn too_large_transaction_is_rolled_back() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let result = fs.transaction(|fs| {
+        let file = fs.create_file(root, "file")?;
+        fs.write_contents_by_entity(root, &file, &vec![4; 64 * 1024], 0)
+    });
+
+    assert!(matches!(result, Err(NoctFSError::TooLarge)));
+    assert!(matches!(
+        fs.find_entity(root, "file"),
+        Err(NoctFSError::NotFound)
+    ));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn needs_journal() {
+    let mut device = small_volume(Some(0));
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    assert!(matches!(
+        fs.transaction(|fs| fs.create_file(root, "file")),
+        Err(NoctFSError::NoJournal)
+    ));
+    assert!(matches!(
+        fs.find_entity(root, "file"),
+        Err(NoctFSError::NotFound)
+    ));
+}
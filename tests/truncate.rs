@@ -0,0 +1,148 @@
+//! Truncating and extending files.
+
+mod common;
+
+use common::{assert_clean, fill, root, small_volume};
+use noctfs::{NoctFS, NoctFSError};
+
+#[test]
+fn shrinking_frees_blocks_past_new_end() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    let free_blocks = fs.stats().unwrap().free_blocks;
+
+    fs.write_contents_by_entity(root, &file, &[1; 3000], 0)
+        .unwrap();
+
+    let file = fs.truncate(root, &file, 700).unwrap();
+
+    assert_eq!(file.size, 700);
+    assert_eq!(fs.get_chain(file.start_block).unwrap().len(), 2);
+    assert_eq!(fs.stats().unwrap().free_blocks, free_blocks - 1);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn cut_off_data_doesnt_come_back() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[7; 1500], 0)
+        .unwrap();
+
+    let file = fs.truncate(root, &file, 100).unwrap();
+    let file = fs.truncate(root, &file, 1500).unwrap();
+    let mut data = [0xFF; 1500];
+
+    fs.read_contents_by_entity(root, &file, &mut data, 0)
+        .unwrap();
+
+    assert!(data[..100].iter().all(|&b| b == 7));
+    assert!(data[100..].iter().all(|&b| b == 0));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn growing_fills_reused_blocks_with_zeroes() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let old = fs.create_file(root, "old").unwrap();
+    fs.write_contents_by_entity(root, &old, &[9; 2000], 0)
+        .unwrap();
+    fs.delete_file(root, &old).unwrap();
+
+    let file = fs.create_file(root, "file").unwrap();
+    let file = fs.truncate(root, &file, 2000).unwrap();
+    let mut data = [0xFF; 2000];
+
+    fs.read_contents_by_entity(root, &file, &mut data, 0)
+        .unwrap();
+
+    assert!(data.iter().all(|&b| b == 0));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn empty_file_keeps_its_first_block() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[1; 1200], 0)
+        .unwrap();
+
+    let file = fs.truncate(root, &file, 0).unwrap();
+
+    assert_eq!(file.size, 0);
+    assert_eq!(fs.get_chain(file.start_block).unwrap().len(), 1);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn set_len_through_file_handle() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    fs.create_file(root, "file").unwrap();
+
+    let mut file = fs.open("/file").unwrap();
+    file.set_len(10).unwrap();
+
+    assert_eq!(file.len(), 10);
+    assert_eq!(file.position(), 0);
+    assert_eq!(fs.lookup("/file").unwrap().size, 10);
+}
+
+#[test]
+fn rejects_directories() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let dir = fs.create_directory(root, "dir").unwrap();
+
+    assert!(matches!(
+        fs.truncate(root, &dir, 0),
+        Err(NoctFSError::IsADirectory)
+    ));
+}
+
+#[test]
+fn growing_on_full_volume_keeps_file() {
+    let mut device = small_volume(None);
+    let mut fs = NoctFS::new(&mut device).unwrap();
+    let root = root(&mut fs);
+
+    let file = fs.create_file(root, "file").unwrap();
+    fs.write_contents_by_entity(root, &file, &[3; 100], 0)
+        .unwrap();
+
+    fill(&mut fs);
+
+    assert!(matches!(
+        fs.truncate(root, &file, 5000),
+        Err(NoctFSError::NoSpace)
+    ));
+
+    let file = fs.find_entity(root, "file").unwrap();
+
+    assert_eq!(file.size, 100);
+    assert_eq!(fs.get_chain(file.start_block).unwrap().len(), 1);
+    assert_clean(&mut fs);
+
+    // Shrinking still works, and gives the space back.
+    let filler = fs.find_entity(root, "filler").unwrap();
+    fs.truncate(root, &filler, 0).unwrap();
+
+    assert!(fs.stats().unwrap().free_blocks > 0);
+    assert_clean(&mut fs);
+}